@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use wcloud::{Tokenizer, WordCloud, WordCloudSize, Word, DEFAULT_EXCLUDE_WORDS_TEXT};
 use nanorand::{Rng, WyRand};
 use palette::{Pixel, Srgb, Hsl, IntoColor};
@@ -7,9 +7,7 @@ use image::{ImageFormat, Rgba};
 use std::time::Instant;
 
 fn main() {
-    let script_text = include_str!("a_new_hope.txt")
-        .replace("HAN", "Han")
-        .replace("LUKE'S", "Luke");
+    let script_text = include_str!("a_new_hope.txt");
 
     let mut filter = DEFAULT_EXCLUDE_WORDS_TEXT.lines()
         .collect::<HashSet<_>>();
@@ -17,8 +15,11 @@ fn main() {
     filter.insert("int");
     filter.insert("ext");
 
+    let aliases = HashMap::from([("han", "Han"), ("luke's", "Luke")]);
+
     let tokenizer = Tokenizer::default()
         .with_max_words(1000)
+        .with_aliases(aliases)
         .with_filter(filter);
 
     let wordcloud = WordCloud::default()
@@ -46,7 +47,7 @@ fn main() {
     };
 
     let now = Instant::now();
-    let wordcloud_image = wordcloud.generate_from_text_with_color_func(&script_text, mask, 1.0, color_func);
+    let wordcloud_image = wordcloud.generate_from_text_with_color_func(script_text, mask, 1.0, color_func);
 
     println!("Generated in {}ms", now.elapsed().as_millis());
 
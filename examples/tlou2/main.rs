@@ -3,6 +3,7 @@ use wcloud::{Tokenizer, WordCloud, WordCloudSize, Word, DEFAULT_EXCLUDE_WORDS_TE
 use nanorand::{Rng, WyRand};
 use palette::{Pixel, Srgb, Hsl, IntoColor};
 use image::{ImageFormat, Rgba};
+use regex::Regex;
 
 use std::time::Instant;
 
@@ -17,7 +18,7 @@ fn main() {
 
     let exclude_words = [
         "oh", "alright", "okay", "gonna", "go", "c'mon", "hey", "em",
-        "maybe", "uh", "Well", "ya", "yeah", "let", "see", "didn",
+        "maybe", "Well", "ya", "yeah", "let", "see", "didn",
         "re", "s", "come", "got", "ll", "right", "ve", "don", "t", "C"
     ];
 
@@ -28,6 +29,7 @@ fn main() {
     let tokenizer = Tokenizer::default()
         .with_max_words(1000)
         .with_filter(filter)
+        .with_filter_patterns(vec![Regex::new("(?i)^u+h+$").unwrap()])
         .with_min_word_length(2);
 
     let max_font_size = Some(150.0);
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use ab_glyph::point;
+use image::{Delay, Frame, ImageError, ImageFormat, ImageResult, RgbaImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::error::{EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use crate::{Layout, PlacedWord};
+
+/// The file formats animations can be saved as
+///
+/// There's no WebP, since the `image` crate can only encode still WebP images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    /// Animated PNG
+    Apng,
+}
+
+impl AnimationFormat {
+    /// Picks the format from the extension of the path, `.gif`, `.png` or `.apng`
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for AnimationFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gif" => Ok(AnimationFormat::Gif),
+            "png" | "apng" => Ok(AnimationFormat::Apng),
+            _ => Err(format!("Unknown animation format '{}'", s)),
+        }
+    }
+}
+
+/// Writes the frames as an animation that loops forever, showing each frame for `frame_delay`
+pub fn encode_animation<W: Write>(writer: W, frames: &[RgbaImage], frame_delay: Duration, format: AnimationFormat) -> ImageResult<()> {
+    assert!(!frames.is_empty(), "An animation needs at least one frame");
+
+    match format {
+        AnimationFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(writer, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+
+            let delay = Delay::from_saturating_duration(frame_delay);
+            encoder.encode_frames(frames.iter().map(|frame| Frame::from_parts(frame.clone(), 0, 0, delay)))
+        },
+        AnimationFormat::Apng => {
+            let to_image_error = |e: png::EncodingError| {
+                ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::Png), e))
+            };
+
+            let (width, height) = frames[0].dimensions();
+            let mut encoder = png::Encoder::new(writer, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            // Zero plays loops forever
+            encoder.set_animated(frames.len() as u32, 0).map_err(to_image_error)?;
+
+            let delay_ms = frame_delay.as_millis().min(u16::MAX as u128) as u16;
+            encoder.set_frame_delay(delay_ms, 1000).map_err(to_image_error)?;
+
+            let mut writer = encoder.write_header().map_err(to_image_error)?;
+            for frame in frames {
+                writer.write_image_data(frame.as_raw()).map_err(to_image_error)?;
+            }
+            writer.finish().map_err(to_image_error)
+        },
+    }
+}
+
+/// Saves the frames as an animation, in the format that matches the extension of the path
+pub fn save_animation(path: impl AsRef<Path>, frames: &[RgbaImage], frame_delay: Duration) -> ImageResult<()> {
+    let path = path.as_ref();
+    let format = AnimationFormat::from_path(path).ok_or_else(|| {
+        ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            ImageFormatHint::PathExtension(path.extension().unwrap_or_default().into()),
+            UnsupportedErrorKind::Format(ImageFormatHint::Unknown),
+        ))
+    })?;
+
+    let file = BufWriter::new(File::create(path)?);
+    encode_animation(file, frames, frame_delay, format)
+}
+
+/// A word as it is in the first and last frames of a transition. Words that are only in one of
+/// the layouts fade in or out where they are
+pub(crate) struct Track<'a> {
+    pub from: Option<&'a PlacedWord>,
+    pub to: Option<&'a PlacedWord>,
+}
+
+impl Track<'_> {
+    /// The word and its opacity at `t`, between 0 at the start and 1 at the end
+    pub fn at(&self, t: f32) -> (PlacedWord, f32) {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => {
+                let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+                let word = PlacedWord {
+                    text: to.text.clone(),
+                    font_size: lerp(from.font_size, to.font_size),
+                    // Rotation can't be blended, so it flips halfway through
+                    rotated: if t < 0.5 { from.rotated } else { to.rotated },
+                    position: point(lerp(from.position.x, to.position.x), lerp(from.position.y, to.position.y)),
+                    frequency: lerp(from.frequency, to.frequency),
+                };
+
+                (word, 1.0)
+            },
+            (Some(from), None) => (from.clone(), 1.0 - t),
+            (None, Some(to)) => (to.clone(), t),
+            (None, None) => unreachable!("A track always has at least one end"),
+        }
+    }
+
+    pub fn word(&self) -> &PlacedWord {
+        self.to.or(self.from).expect("A track always has at least one end")
+    }
+}
+
+/// Pairs up the words of both layouts by their text, matching repeated words in the order they
+/// were placed
+pub(crate) fn match_words<'a>(from: &'a Layout, to: &'a Layout) -> Vec<Track<'a>> {
+    let mut matched = vec![false; from.words.len()];
+
+    let mut tracks: Vec<Track> = to.words.iter()
+        .map(|word| {
+            let previous = (0..from.words.len())
+                .find(|&i| !matched[i] && from.words[i].text == word.text);
+
+            if let Some(i) = previous {
+                matched[i] = true;
+            }
+
+            Track { from: previous.map(|i| &from.words[i]), to: Some(word) }
+        })
+        .collect();
+
+    tracks.extend(
+        from.words.iter()
+            .zip(matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(word, _)| Track { from: Some(word), to: None })
+    );
+
+    tracks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use image::AnimationDecoder;
+    use image::codecs::gif::GifDecoder;
+
+    fn word(text: &str, x: f32, font_size: f32) -> PlacedWord {
+        PlacedWord { text: text.to_string(), font_size, rotated: false, position: point(x, 10.0), frequency: 1.0 }
+    }
+
+    #[test]
+    fn words_move_and_fade() {
+        let layout = |words| Layout { width: 100, height: 100, words, dropped_words: Vec::new(), coverage: 0.0, fill_words: 0 };
+        let from = layout(vec![word("rust", 0.0, 10.0), word("gone", 50.0, 10.0), word("rust", 20.0, 10.0)]);
+        let to = layout(vec![word("rust", 40.0, 30.0), word("new", 5.0, 10.0)]);
+
+        let tracks = match_words(&from, &to);
+        assert_eq!(tracks.len(), 4);
+
+        let (moving, opacity) = tracks[0].at(0.5);
+        assert_eq!((moving.position.x, moving.font_size, opacity), (20.0, 20.0, 1.0));
+        assert_eq!(tracks[1].at(0.25).1, 0.25);
+        assert_eq!(tracks[2].word().text, "gone");
+        assert_eq!(tracks[3].word().position.x, 20.0);
+        assert_eq!(tracks[3].at(1.0).1, 0.0);
+    }
+
+    #[test]
+    fn encodes_every_frame() {
+        let frames: Vec<RgbaImage> = (0..3)
+            .map(|i| RgbaImage::from_pixel(8, 6, image::Rgba([i * 100, 0, 0, 255])))
+            .collect();
+
+        let mut gif = Vec::new();
+        encode_animation(&mut gif, &frames, Duration::from_millis(40), AnimationFormat::Gif).unwrap();
+        let decoded = GifDecoder::new(Cursor::new(gif)).unwrap().into_frames().count();
+        assert_eq!(decoded, 3);
+
+        let mut apng = Vec::new();
+        encode_animation(&mut apng, &frames, Duration::from_millis(40), AnimationFormat::Apng).unwrap();
+        let reader = png::Decoder::new(Cursor::new(apng)).read_info().unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+
+        assert_eq!(AnimationFormat::from_path("out.gif"), Some(AnimationFormat::Gif));
+        assert_eq!(AnimationFormat::from_path("out.webp"), None);
+    }
+}
@@ -0,0 +1,207 @@
+use std::str::FromStr;
+use image::GrayImage;
+use nanorand::{Rng, WyRand};
+use crate::sat::{Point, Rect};
+
+/// How likely each free position is to be picked for a word
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum PlacementBias {
+    /// Every free position is equally likely
+    #[default]
+    Uniform,
+    /// Positions near the center of the canvas are more likely, falling off like a Gaussian
+    /// whose standard deviation is `spread` times the canvas size
+    Center { spread: f32 },
+    /// Positions are weighted by the brightness of the image at the center of the word. The image
+    /// is stretched to the size of the canvas, and black areas are never picked
+    Density(GrayImage),
+}
+
+impl PlacementBias {
+    /// The weight of a word centered at (`x`, `y`) on a canvas of the given size
+    pub fn weight(&self, x: f32, y: f32, width: u32, height: u32) -> f32 {
+        match self {
+            PlacementBias::Uniform => 1.0,
+            PlacementBias::Center { spread } => {
+                let dx = (x - width as f32 / 2.0) / (spread * width as f32);
+                let dy = (y - height as f32 / 2.0) / (spread * height as f32);
+                (-(dx * dx + dy * dy) / 2.0).exp()
+            },
+            PlacementBias::Density(image) => {
+                let image_x = (x * image.width() as f32 / width as f32) as u32;
+                let image_y = (y * image.height() as f32 / height as f32) as u32;
+                let pixel = image.get_pixel(image_x.min(image.width() - 1), image_y.min(image.height() - 1));
+                pixel.0[0] as f32 / 255.0
+            },
+        }
+    }
+}
+
+/// The spread of [`PlacementBias::Center`] when it isn't given
+const DEFAULT_CENTER_SPREAD: f32 = 0.25;
+
+/// Parses `uniform`, `center` or `center:SPREAD`. A density map has to be loaded as an image
+impl FromStr for PlacementBias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        match lower.split_once(':') {
+            None if lower == "uniform" => Ok(PlacementBias::Uniform),
+            None if lower == "center" => Ok(PlacementBias::Center { spread: DEFAULT_CENTER_SPREAD }),
+            Some(("center", spread)) => match spread.parse::<f32>() {
+                Ok(spread) if spread > 0.0 => Ok(PlacementBias::Center { spread }),
+                _ => Err(format!("The spread in '{}' must be a positive number", s)),
+            },
+            _ => Err(format!("Unknown placement bias '{}'", s)),
+        }
+    }
+}
+
+/// Picks one of the positions it is shown at random, in proportion to their weights
+///
+/// https://en.wikipedia.org/wiki/Reservoir_sampling
+pub(crate) struct Reservoir<'a> {
+    /// `None` picks uniformly
+    bias: Option<&'a PlacementBias>,
+    pub rng: &'a mut WyRand,
+    rect_width: u32,
+    rect_height: u32,
+    canvas_width: u32,
+    canvas_height: u32,
+    count: u64,
+    total_weight: f64,
+    pub point: Option<Point>,
+}
+
+impl<'a> Reservoir<'a> {
+    pub fn new(bias: Option<&'a PlacementBias>, rect: &Rect, canvas_width: u32, canvas_height: u32, rng: &'a mut WyRand) -> Self {
+        let bias = bias.filter(|bias| **bias != PlacementBias::Uniform);
+
+        Reservoir {
+            bias,
+            rng,
+            rect_width: rect.width,
+            rect_height: rect.height,
+            canvas_width,
+            canvas_height,
+            count: 0,
+            total_weight: 0.0,
+            point: None,
+        }
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.bias.is_none()
+    }
+
+    /// How many positions have been added, when picking uniformly
+    #[cfg(test)]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn add(&mut self, x: u32, y: u32) {
+        match self.bias {
+            None => {
+                let picked = match u32::try_from(self.count) {
+                    Ok(count) => self.rng.generate_range(0..=count) == count,
+                    Err(_) => self.rng.generate_range(0..=self.count) == self.count,
+                };
+                if picked {
+                    self.point = Some(Point { x, y });
+                }
+                self.count += 1;
+            },
+            Some(bias) => {
+                let center_x = x as f32 + self.rect_width as f32 / 2.0;
+                let center_y = y as f32 + self.rect_height as f32 / 2.0;
+                let weight = bias.weight(center_x, center_y, self.canvas_width, self.canvas_height) as f64;
+
+                if weight <= 0.0 {
+                    return;
+                }
+
+                self.total_weight += weight;
+                if self.rng.generate::<f64>() * self.total_weight < weight {
+                    self.point = Some(Point { x, y });
+                }
+            },
+        }
+    }
+
+    /// Adds every position in the block at once
+    pub fn add_block(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        if self.bias.is_some() {
+            for block_y in y..y + height {
+                for block_x in x..x + width {
+                    self.add(block_x, block_y);
+                }
+            }
+            return;
+        }
+
+        let count = width as u64 * height as u64;
+        self.count += count;
+
+        let random_num = self.rng.generate_range(0..self.count);
+        if random_num < count {
+            let index = self.rng.generate_range(0..count);
+            self.point = Some(Point {
+                x: x + (index % width as u64) as u32,
+                y: y + (index / width as u64) as u32,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn center_bias_prefers_the_middle() {
+        let bias = PlacementBias::Center { spread: 0.1 };
+        assert_eq!(bias.weight(50.0, 50.0, 100, 100), 1.0);
+        assert!(bias.weight(90.0, 50.0, 100, 100) < 0.2);
+
+        let rect = Rect { width: 10, height: 10 };
+        let mut rng = WyRand::new_seed(1);
+        let mut near_center = 0;
+
+        for _ in 0..200 {
+            let mut reservoir = Reservoir::new(Some(&bias), &rect, 100, 100, &mut rng);
+            reservoir.add_block(0, 0, 90, 90);
+            let point = reservoir.point.unwrap();
+            if (25..65).contains(&point.x) && (25..65).contains(&point.y) {
+                near_center += 1;
+            }
+        }
+
+        // About a fifth of the positions, but most of the weight
+        assert!(near_center > 150);
+    }
+
+    #[test]
+    fn parses_center_spread() {
+        assert_eq!("center".parse(), Ok(PlacementBias::Center { spread: DEFAULT_CENTER_SPREAD }));
+        assert_eq!("center:0.1".parse(), Ok(PlacementBias::Center { spread: 0.1 }));
+        assert!("center:-1".parse::<PlacementBias>().is_err());
+        assert!("edges".parse::<PlacementBias>().is_err());
+    }
+
+    #[test]
+    fn density_bias_skips_black() {
+        let mut density = GrayImage::new(2, 1);
+        density.put_pixel(1, 0, image::Luma([255]));
+        let bias = PlacementBias::Density(density);
+
+        let rect = Rect { width: 2, height: 2 };
+        let mut rng = WyRand::new_seed(1);
+        for _ in 0..50 {
+            let mut reservoir = Reservoir::new(Some(&bias), &rect, 100, 10, &mut rng);
+            reservoir.add_block(0, 0, 98, 8);
+            assert!(reservoir.point.unwrap().x >= 49);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::str::FromStr;
+
+/// Programming languages whose keywords can be excluded from a word cloud
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Language {
+    C,
+    Cpp,
+    Go,
+    Java,
+    JavaScript,
+    Python,
+    Rust,
+    TypeScript,
+}
+
+impl Language {
+    pub fn keywords(&self) -> &'static [&'static str] {
+        match self {
+            Language::C => &[
+                "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+                "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long",
+                "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct",
+                "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "include",
+                "define", "ifdef", "ifndef", "endif", "NULL",
+            ],
+            Language::Cpp => &[
+                "alignas", "alignof", "auto", "bool", "break", "case", "catch", "char", "class",
+                "const", "constexpr", "const_cast", "continue", "decltype", "default", "delete",
+                "do", "double", "dynamic_cast", "else", "enum", "explicit", "export", "extern",
+                "false", "float", "for", "friend", "goto", "if", "inline", "int", "long", "mutable",
+                "namespace", "new", "noexcept", "nullptr", "operator", "private", "protected",
+                "public", "register", "reinterpret_cast", "return", "short", "signed", "sizeof",
+                "static", "static_assert", "static_cast", "struct", "switch", "template", "this",
+                "throw", "true", "try", "typedef", "typeid", "typename", "union", "unsigned",
+                "using", "virtual", "void", "volatile", "while", "include", "define", "std",
+            ],
+            Language::Go => &[
+                "break", "case", "chan", "const", "continue", "default", "defer", "else",
+                "fallthrough", "for", "func", "go", "goto", "if", "import", "interface", "map",
+                "package", "range", "return", "select", "struct", "switch", "type", "var", "nil",
+                "true", "false",
+            ],
+            Language::Java => &[
+                "abstract", "assert", "boolean", "break", "byte", "case", "catch", "char", "class",
+                "const", "continue", "default", "do", "double", "else", "enum", "extends", "final",
+                "finally", "float", "for", "goto", "if", "implements", "import", "instanceof",
+                "int", "interface", "long", "native", "new", "package", "private", "protected",
+                "public", "return", "short", "static", "strictfp", "super", "switch",
+                "synchronized", "this", "throw", "throws", "transient", "try", "void", "volatile",
+                "while", "var", "true", "false", "null",
+            ],
+            Language::JavaScript => &[
+                "async", "await", "break", "case", "catch", "class", "const", "continue",
+                "debugger", "default", "delete", "do", "else", "export", "extends", "false",
+                "finally", "for", "function", "if", "import", "in", "instanceof", "let", "new",
+                "null", "return", "static", "super", "switch", "this", "throw", "true", "try",
+                "typeof", "undefined", "var", "void", "while", "with", "yield",
+            ],
+            Language::Python => &[
+                "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+                "continue", "def", "del", "elif", "else", "except", "finally", "for", "from",
+                "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass",
+                "raise", "return", "try", "while", "with", "yield", "self", "cls",
+            ],
+            Language::Rust => &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+                "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                "super", "trait", "true", "type", "unsafe", "use", "where", "while", "Some", "None",
+                "Ok", "Err",
+            ],
+            Language::TypeScript => &[
+                "abstract", "any", "as", "async", "await", "boolean", "break", "case", "catch",
+                "class", "const", "constructor", "continue", "declare", "default", "delete", "do",
+                "else", "enum", "export", "extends", "false", "finally", "for", "from", "function",
+                "if", "implements", "import", "in", "instanceof", "interface", "keyof", "let",
+                "module", "namespace", "never", "new", "null", "number", "private", "protected",
+                "public", "readonly", "return", "static", "string", "super", "switch", "this",
+                "throw", "true", "try", "type", "typeof", "undefined", "unknown", "var", "void",
+                "while", "yield",
+            ],
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "c" => Ok(Language::C),
+            "cpp" | "c++" => Ok(Language::Cpp),
+            "go" => Ok(Language::Go),
+            "java" => Ok(Language::Java),
+            "javascript" | "js" => Ok(Language::JavaScript),
+            "python" | "py" => Ok(Language::Python),
+            "rust" | "rs" => Ok(Language::Rust),
+            "typescript" | "ts" => Ok(Language::TypeScript),
+            _ => Err(format!("Unknown language '{}'", s)),
+        }
+    }
+}
+
+/// Splits a source code identifier into the words it is made of
+///
+/// Handles `camelCase`, `PascalCase`, `snake_case`, `SCREAMING_CASE` and `kebab-case`,
+/// keeping acronyms together (`HTTPServer` becomes `HTTP` and `Server`).
+pub fn split_identifier(identifier: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+
+    let separators = |c: char| c == '_' || c == '-' || c == '$';
+    for segment in identifier.split(separators) {
+        // Quotes that ended up in the match are string delimiters, not part of the word
+        let segment = segment.trim_matches('\'');
+
+        let chars: Vec<(usize, char)> = segment.char_indices().collect();
+        let mut start = 0;
+
+        for i in 1..chars.len() {
+            let (index, c) = chars[i];
+            let prev = chars[i - 1].1;
+            let next = chars.get(i + 1).map(|(_, c)| *c);
+
+            let lower_to_upper = c.is_uppercase() && (prev.is_lowercase() || prev.is_numeric());
+            let end_of_acronym = c.is_uppercase() && prev.is_uppercase()
+                && next.is_some_and(char::is_lowercase);
+
+            if lower_to_upper || end_of_acronym {
+                parts.push(&segment[start..index]);
+                start = index;
+            }
+        }
+
+        if start < segment.len() {
+            parts.push(&segment[start..]);
+        }
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_identifiers() {
+        assert_eq!(split_identifier("camelCaseWord"), vec!["camel", "Case", "Word"]);
+        assert_eq!(split_identifier("PascalCase"), vec!["Pascal", "Case"]);
+        assert_eq!(split_identifier("snake_case_word"), vec!["snake", "case", "word"]);
+        assert_eq!(split_identifier("SCREAMING_CASE"), vec!["SCREAMING", "CASE"]);
+        assert_eq!(split_identifier("kebab-case"), vec!["kebab", "case"]);
+        assert_eq!(split_identifier("HTTPServerError"), vec!["HTTP", "Server", "Error"]);
+        assert_eq!(split_identifier("base64Encode"), vec!["base64", "Encode"]);
+        assert_eq!(split_identifier("__init__"), vec!["init"]);
+        assert_eq!(split_identifier("docstring'''"), vec!["docstring"]);
+    }
+}
@@ -1,24 +1,48 @@
+use std::borrow::Cow;
+use std::fmt;
 use std::fs;
+use std::sync::{Mutex, MutexGuard};
 use std::path::{PathBuf};
 use image::{GrayImage, Luma, RgbaImage, Rgba};
-use ab_glyph::{PxScale, Point, point, FontVec, Font, Glyph};
+use ab_glyph::{PxScale, Point, point, FontVec};
 use palette::{Pixel, Srgb, Hsl, IntoColor};
 use std::process::exit;
+use std::str::FromStr;
+use std::time::Instant;
 use woff2::decode::{convert_woff2_to_ttf, is_woff2};
 
 mod text;
-use text::GlyphData;
+use text::{GlyphCache, GlyphData};
 pub mod sat;
 mod tokenizer;
-pub use tokenizer::{Tokenizer, DEFAULT_EXCLUDE_WORDS_TEXT};
+pub use tokenizer::{Tokenizer, Tokenize, TokenMode, CaseStrategy, parse_aliases, parse_word_list, DEFAULT_EXCLUDE_WORDS_TEXT};
+mod identifier;
+pub use identifier::Language;
+mod scaling;
+pub use scaling::FontSizeMapping;
+mod report;
+pub use report::PlacementReport;
+mod occupancy;
+pub use occupancy::OccupancyBackend;
+use occupancy::Occupancy;
+mod bias;
+pub use bias::PlacementBias;
+use bias::Reservoir;
+mod reserved;
+pub use reserved::ReservedRegion;
+mod animation;
+pub use animation::{AnimationFormat, encode_animation, save_animation};
 
 use nanorand::{Rng, WyRand};
-use crate::sat::{Rect, Region};
+use crate::sat::{Rect, Region, Span};
 
+mod observer;
+pub use observer::PlacementObserver;
 #[cfg(feature = "visualize")]
-mod visualize;
+pub use observer::JsonObserver;
+
 #[cfg(feature = "visualize")]
-use crate::visualize::{Init, Message};
+mod visualize;
 
 pub struct Word<'a> {
     pub text: &'a str,
@@ -31,6 +55,193 @@ pub struct Word<'a> {
     pub index: usize,
 }
 
+/// A word that has been given a size and position in a [`Layout`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedWord {
+    pub text: String,
+    pub font_size: f32,
+    pub rotated: bool,
+    pub position: Point,
+    pub frequency: f32,
+}
+
+/// A word drawn at a fixed size and position before the other words are placed around it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinnedWord {
+    pub text: String,
+    /// Where the center of the word goes on the canvas
+    pub center: (f32, f32),
+    pub font_size: f32,
+    pub rotated: bool,
+}
+
+/// Parses `WORD@X,Y,SIZE`, followed by `,rotated` to rotate the word
+impl FromStr for PinnedWord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pinned word '{}', expected WORD@X,Y,SIZE or WORD@X,Y,SIZE,rotated", s);
+
+        let (text, values) = s.rsplit_once('@').ok_or_else(invalid)?;
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+
+        let rotated = match values.get(3) {
+            None => false,
+            Some(&"rotated") => true,
+            Some(_) => return Err(invalid()),
+        };
+        if text.is_empty() || values.len() < 3 || values.len() > 4 {
+            return Err(invalid());
+        }
+
+        let parse = |value: &str| value.parse::<f32>().map_err(|_| invalid());
+
+        Ok(PinnedWord {
+            text: text.to_string(),
+            center: (parse(values[0])?, parse(values[1])?),
+            font_size: parse(values[2])?,
+            rotated,
+        })
+    }
+}
+
+/// Why a word was left out of a [`Layout`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropReason {
+    /// The word is larger than the canvas even at the minimum font size
+    TooBig,
+    /// There was no free space left for the word at the minimum font size
+    NoSpace,
+    /// The word's font size scaled below the minimum font size
+    BelowMinFontSize,
+    /// Placement stopped at an earlier word, see [`WordCloud::with_skip_unplaceable_words`]
+    NotAttempted,
+    /// A pinned word would stick out of the canvas where it was pinned
+    OffCanvas,
+}
+
+impl DropReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::TooBig => "too-big",
+            DropReason::NoSpace => "no-space",
+            DropReason::BelowMinFontSize => "below-min-font-size",
+            DropReason::NotAttempted => "not-attempted",
+            DropReason::OffCanvas => "off-canvas",
+        }
+    }
+}
+
+/// A word that could not be placed in a [`Layout`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedWord {
+    pub text: String,
+    pub frequency: f32,
+    pub reason: DropReason,
+}
+
+/// Where each word of a word cloud ends up, before anything is drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub words: Vec<PlacedWord>,
+    /// The words that didn't fit, heaviest first
+    pub dropped_words: Vec<DroppedWord>,
+    /// The fraction of the usable canvas area that words were drawn on
+    pub coverage: f32,
+    /// How many of the placed words, at the end of `words`, are copies placed by
+    /// [`WordCloud::with_fill`]
+    pub fill_words: usize,
+}
+
+/// Writes the layout as text that can be read back with [`Layout::from_str`], to be passed to
+/// [`WordCloud::with_previous_layout`] later
+///
+/// The first line is the width and height of the canvas, followed by a line per placed word
+/// with its position, font size, rotation, frequency and text. Dropped words, the coverage
+/// and which words are fill copies are not written.
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.width, self.height)?;
+
+        for word in &self.words {
+            writeln!(f, "{} {} {} {} {} {}", word.position.x, word.position.y, word.font_size, word.rotated, word.frequency, word.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().filter(|line| !line.trim().is_empty());
+
+        let header = lines.next().ok_or("The layout is empty")?;
+        let (width, height) = header.split_once(' ')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.trim().parse().ok()?)))
+            .ok_or_else(|| format!("Invalid layout size '{}'", header))?;
+
+        let words = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.splitn(6, ' ').collect();
+                let parse_word = || -> Option<PlacedWord> {
+                    Some(PlacedWord {
+                        position: point(fields.first()?.parse().ok()?, fields.get(1)?.parse().ok()?),
+                        font_size: fields.get(2)?.parse().ok()?,
+                        rotated: fields.get(3)?.parse().ok()?,
+                        frequency: fields.get(4)?.parse().ok()?,
+                        text: fields.get(5)?.to_string(),
+                    })
+                };
+
+                parse_word().ok_or_else(|| format!("Invalid layout word '{}'", line))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Layout { width, height, words, dropped_words: Vec::new(), coverage: 0.0, fill_words: 0 })
+    }
+}
+
+/// How the font size of a word is shrunk when there's no space for it
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FontSearch {
+    /// Tries every size from the largest down, one font step at a time
+    #[default]
+    Linear,
+    /// Bisects between the largest size and the minimum font size, which needs far fewer
+    /// searches for space on big canvases
+    Bisect,
+}
+
+impl FromStr for FontSearch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(FontSearch::Linear),
+            "bisect" => Ok(FontSearch::Bisect),
+            _ => Err(format!("Unknown font search '{}'", s)),
+        }
+    }
+}
+
+/// The free space words are placed into
+#[derive(Clone)]
+struct Canvas {
+    occupancy: Occupancy,
+    /// The outline of the mask, if there is one
+    mask_spans: Option<Vec<Vec<Span>>>,
+    usable_pixels: usize,
+    /// Whether the observers hear about words placed here. Off for attempts that may be thrown away
+    observed: bool,
+}
+
+/// How many times [`WordCloud::with_required_words`] retries with a smaller starting font size
+const FIT_ATTEMPTS: usize = 12;
+
 // TODO: Figure out a better way to structure this
 pub enum WordCloudSize {
     FromDimensions { width: u32, height: u32 },
@@ -38,7 +249,7 @@ pub enum WordCloudSize {
 }
 
 pub struct WordCloud {
-    tokenizer: Tokenizer,
+    tokenizer: Box<dyn Tokenize + Send + Sync>,
     background_color: Rgba<u8>,
     pub font: FontVec,
     min_font_size: f32,
@@ -47,7 +258,20 @@ pub struct WordCloud {
     word_margin: u32,
     word_rotate_chance: f64,
     relative_font_scaling: f32,
+    font_size_mapping: FontSizeMapping,
+    font_search: FontSearch,
+    occupancy_backend: OccupancyBackend,
+    placement_bias: PlacementBias,
+    reserved_regions: Vec<ReservedRegion>,
+    pinned_words: Vec<PinnedWord>,
+    previous_layout: Option<Layout>,
+    skip_unplaceable_words: bool,
+    fill: Option<f32>,
+    required_words: Option<usize>,
     rng_seed: Option<u64>,
+    /// Shaped words and rasterized glyphs, kept between generations
+    glyph_cache: Mutex<GlyphCache>,
+    observers: Mutex<Vec<Box<dyn PlacementObserver>>>,
 }
 
 impl Default for WordCloud {
@@ -55,7 +279,7 @@ impl Default for WordCloud {
         let font = FontVec::try_from_vec(include_bytes!("../fonts/Ubuntu-B.ttf").to_vec()).unwrap();
 
         WordCloud {
-            tokenizer: Tokenizer::default(),
+            tokenizer: Box::new(Tokenizer::default()),
             background_color: Rgba([0, 0, 0, 255]),
             font,
             min_font_size: 4.0,
@@ -64,14 +288,26 @@ impl Default for WordCloud {
             word_margin: 2,
             word_rotate_chance: 0.10,
             relative_font_scaling: 0.5,
+            font_size_mapping: FontSizeMapping::Relative,
+            font_search: FontSearch::Linear,
+            occupancy_backend: OccupancyBackend::SummedAreaTable,
+            placement_bias: PlacementBias::Uniform,
+            reserved_regions: Vec::new(),
+            pinned_words: Vec::new(),
+            previous_layout: None,
+            skip_unplaceable_words: false,
+            fill: None,
+            required_words: None,
             rng_seed: None,
+            glyph_cache: Mutex::new(GlyphCache::default()),
+            observers: Mutex::new(Vec::new()),
         }
     }
 }
 
 impl WordCloud {
-    pub fn with_tokenizer(mut self, value: Tokenizer) -> Self {
-        self.tokenizer = value;
+    pub fn with_tokenizer(mut self, value: impl Tokenize + Send + Sync + 'static) -> Self {
+        self.tokenizer = Box::new(value);
         self
     }
     pub fn with_background_color(mut self, value: Rgba<u8>) -> Self {
@@ -123,6 +359,78 @@ impl WordCloud {
         self.relative_font_scaling = value;
         self
     }
+    /// Sets how word weights are mapped to font sizes. Relative font scaling only applies to
+    /// [`FontSizeMapping::Relative`]
+    pub fn with_font_size_mapping(mut self, value: FontSizeMapping) -> Self {
+        self.font_size_mapping = value;
+        self
+    }
+    pub fn with_font_search(mut self, value: FontSearch) -> Self {
+        self.font_search = value;
+        self
+    }
+    /// Sets how taken pixels are tracked. [`OccupancyBackend::Bitmap`] uses far less memory on
+    /// large canvases
+    pub fn with_occupancy_backend(mut self, value: OccupancyBackend) -> Self {
+        self.occupancy_backend = value;
+        self
+    }
+    /// Makes some free positions more likely to be picked than others, such as the ones near the
+    /// center of the canvas
+    pub fn with_placement_bias(mut self, value: PlacementBias) -> Self {
+        if let PlacementBias::Center { spread } = value {
+            assert!(spread > 0.0, "The spread of the center bias must be positive");
+        }
+        self.placement_bias = value;
+        self
+    }
+    /// Keeps words out of an area of the canvas, given in canvas coordinates. Can be called more
+    /// than once to reserve several areas, and works with or without a mask
+    pub fn with_reserved_region(mut self, value: ReservedRegion) -> Self {
+        self.reserved_regions.push(value);
+        self
+    }
+    /// Draws a word at a fixed size and position before any other word is placed. The word is
+    /// left out of the normal placement, and is drawn even if it overlaps the mask.
+    /// Can be called more than once
+    pub fn with_pinned_word(mut self, value: PinnedWord) -> Self {
+        self.pinned_words.push(value);
+        self
+    }
+    /// Keeps the words that were in a previous layout where they were, at their new size, if
+    /// there's space for them there. New words, and words that no longer fit where they were,
+    /// are placed as usual
+    pub fn with_previous_layout(mut self, value: Option<Layout>) -> Self {
+        self.previous_layout = value;
+        self
+    }
+    /// Keeps placing the remaining words after one doesn't fit, instead of stopping there.
+    /// The words that were left out are listed in [`Layout::dropped_words`]
+    pub fn with_skip_unplaceable_words(mut self, value: bool) -> Self {
+        self.skip_unplaceable_words = value;
+        self
+    }
+    /// After the words have been placed, keeps placing copies of them at decreasing sizes until
+    /// no more than this fraction of the usable space is free, or until they only fit below the
+    /// minimum font size. Unlike [`Tokenizer::with_repeat`], this knows how much space is left
+    pub fn with_fill(mut self, value: Option<f32>) -> Self {
+        if let Some(fill) = value {
+            assert!((0.0..=1.0).contains(&fill), "The fill target must be between 0 and 1");
+        }
+        self.fill = value;
+        self
+    }
+    /// Makes sure the heaviest words all appear by shrinking the starting font size until they fit.
+    /// If they can't all fit even at the minimum font size, the words are laid out starting from it
+    pub fn with_required_words(mut self, value: Option<usize>) -> Self {
+        self.required_words = value;
+        self
+    }
+    /// Tells the observer about each step of laying out the words. Can be called more than once
+    pub fn with_observer(mut self, value: impl PlacementObserver + 'static) -> Self {
+        self.observers.get_mut().expect("Placement observers were poisoned").push(Box::new(value));
+        self
+    }
     pub fn with_rng_seed(mut self, value: u64) -> Self {
         self.rng_seed.replace(value);
         self
@@ -130,14 +438,12 @@ impl WordCloud {
 }
 
 impl WordCloud {
-    fn generate_from_word_positions(
-        rng: &mut WyRand,
-        width: u32,
-        height: u32,
-        word_positions: Vec<Word>,
+    fn render(
+        &self,
+        layout: &Layout,
         scale: f32,
-        background_color: Rgba<u8>,
-        color_func: fn(&Word, &mut WyRand) -> Rgba<u8>
+        color_func: fn(&Word, &mut WyRand) -> Rgba<u8>,
+        rng: &mut WyRand,
     ) -> RgbaImage {
         // TODO: Refactor this so that we can fail earlier
         if !(0.0..=100.0).contains(&scale) {
@@ -146,25 +452,159 @@ impl WordCloud {
             exit(1);
         }
 
-        let mut final_image_buffer = RgbaImage::from_pixel((width as f32 * scale) as u32, (height as f32 * scale) as u32, background_color);
+        let mut final_image_buffer = RgbaImage::from_pixel((layout.width as f32 * scale) as u32, (layout.height as f32 * scale) as u32, self.background_color);
 
-        for mut word in word_positions.into_iter() {
+        for (index, placed_word) in layout.words.iter().enumerate() {
+            let word = self.word(placed_word, index);
             let col = color_func(&word, rng);
 
-            if scale != 1.0 {
-                word.font_size.x *= scale;
-                word.font_size.y *= scale;
+            self.draw_word(&mut final_image_buffer, word, scale, col, 1.0);
+        }
 
-                word.position.x *= scale;
-                word.position.y *= scale;
+        final_image_buffer
+    }
 
-                word.glyphs = text::text_to_glyphs(word.text, word.font, word.font_size);
+    fn word<'a>(&'a self, placed_word: &'a PlacedWord, index: usize) -> Word<'a> {
+        let font_size = PxScale::from(placed_word.font_size);
+
+        Word {
+            text: &placed_word.text,
+            font: &self.font,
+            font_size,
+            glyphs: self.glyph_cache().text_to_glyphs(&placed_word.text, &self.font, font_size),
+            rotated: placed_word.rotated,
+            position: placed_word.position,
+            frequency: placed_word.frequency,
+            index,
+        }
+    }
+
+    /// Draws a single word, only holding on to the glyph cache while it does so that other word
+    /// clouds sharing this one can render at the same time
+    fn draw_word(&self, image: &mut RgbaImage, mut word: Word, scale: f32, col: Rgba<u8>, opacity: f32) {
+        let mut glyph_cache = self.glyph_cache();
+
+        if scale != 1.0 {
+            word.font_size.x *= scale;
+            word.font_size.y *= scale;
+
+            word.position.x *= scale;
+            word.position.y *= scale;
+
+            word.glyphs = glyph_cache.text_to_glyphs(word.text, word.font, word.font_size);
+        }
+
+        text::draw_glyphs_to_rgba_buffer(image, word.glyphs, word.font, &mut glyph_cache, word.position, word.rotated, col, opacity);
+    }
+
+    /// Draws the words of the layout one batch at a time, in the order they were placed, starting
+    /// from an empty canvas. Each frame adds `words_per_frame` words to the previous one, and the
+    /// last frame has every word. The frames can be saved with [`save_animation`]
+    pub fn render_placement(
+        &self,
+        layout: &Layout,
+        words_per_frame: usize,
+        scale: f32,
+        color_func: fn(&Word, &mut WyRand) -> Rgba<u8>
+    ) -> Vec<RgbaImage> {
+        assert!(words_per_frame > 0, "At least one word has to be added per frame");
+
+        let mut rng = self.rng();
+
+        let mut image = RgbaImage::from_pixel((layout.width as f32 * scale) as u32, (layout.height as f32 * scale) as u32, self.background_color);
+        let mut frames = vec![image.clone()];
+
+        for (index, placed_word) in layout.words.iter().enumerate() {
+            let word = self.word(placed_word, index);
+            let col = color_func(&word, &mut rng);
+
+            self.draw_word(&mut image, word, scale, col, 1.0);
+
+            if (index + 1) % words_per_frame == 0 || index + 1 == layout.words.len() {
+                frames.push(image.clone());
             }
+        }
 
-            text::draw_glyphs_to_rgba_buffer(&mut final_image_buffer, word.glyphs, word.font, word.position, word.rotated, col);
+        frames
+    }
+
+    /// Draws `frame_count` frames that move and resize the words of `from` into their places in
+    /// `to`. Words that are only in one of the layouts fade in or out, and every word keeps its
+    /// color throughout. The frames can be saved with [`save_animation`]
+    pub fn render_transition(
+        &self,
+        from: &Layout,
+        to: &Layout,
+        frame_count: usize,
+        scale: f32,
+        color_func: fn(&Word, &mut WyRand) -> Rgba<u8>
+    ) -> Vec<RgbaImage> {
+        assert!(frame_count > 0, "A transition needs at least one frame");
+
+        // The canvas may have changed size since
+        let from = if (from.width, from.height) == (to.width, to.height) {
+            Cow::Borrowed(from)
         }
+        else {
+            let (scale_x, scale_y) = (to.width as f32 / from.width as f32, to.height as f32 / from.height as f32);
+            let mut scaled = from.clone();
+            for word in &mut scaled.words {
+                word.position = point(word.position.x * scale_x, word.position.y * scale_y);
+            }
+            Cow::Owned(scaled)
+        };
 
-        final_image_buffer
+        let mut rng = self.rng();
+
+        let tracks = animation::match_words(&from, to);
+        let colors: Vec<Rgba<u8>> = tracks.iter()
+            .enumerate()
+            .map(|(index, track)| color_func(&self.word(track.word(), index), &mut rng))
+            .collect();
+
+        (0..frame_count)
+            .map(|frame| {
+                let t = if frame_count > 1 { frame as f32 / (frame_count - 1) as f32 } else { 1.0 };
+                let mut image = RgbaImage::from_pixel((to.width as f32 * scale) as u32, (to.height as f32 * scale) as u32, self.background_color);
+
+                for (index, (track, col)) in tracks.iter().zip(&colors).enumerate() {
+                    let (placed_word, opacity) = track.at(t);
+                    if opacity <= 0.0 {
+                        continue;
+                    }
+
+                    let mut word = self.word(&placed_word, index);
+
+                    // A word switching rotation halfway could stick out of the canvas
+                    let (width, height) = if word.rotated {
+                        (word.glyphs.height, word.glyphs.width)
+                    }
+                    else {
+                        (word.glyphs.width, word.glyphs.height)
+                    };
+                    word.position.x = word.position.x.min(to.width.saturating_sub(width) as f32).max(0.0);
+                    word.position.y = word.position.y.min(to.height.saturating_sub(height) as f32).max(0.0);
+
+                    self.draw_word(&mut image, word, scale, *col, opacity);
+                }
+
+                image
+            })
+            .collect()
+    }
+
+    fn glyph_cache(&self) -> MutexGuard<'_, GlyphCache> {
+        self.glyph_cache.lock().expect("Glyph cache was poisoned")
+    }
+
+    fn observers(&self) -> MutexGuard<'_, Vec<Box<dyn PlacementObserver>>> {
+        self.observers.lock().expect("Placement observers were poisoned")
+    }
+
+    fn notify(&self, mut event: impl FnMut(&mut dyn PlacementObserver)) {
+        for observer in self.observers().iter_mut() {
+            event(observer.as_mut());
+        }
     }
 
     fn check_font_size(font_size: &mut f32, font_step: f32, min_font_size: f32) -> bool {
@@ -179,18 +619,8 @@ impl WordCloud {
         }
     }
 
-    fn glyphs_height(&self, glyphs: &[Glyph]) -> u32 {
-        glyphs.iter().map(|g| {
-            let outlined = self.font.outline_glyph(g.clone())
-                .expect("Unable to outline glyph");
-
-            let bounds = outlined.px_bounds();
-            bounds.height() as u32
-        }).max().expect("No glyphs!")
-    }
-
     fn text_dimensions_at_font_size(&self, text: &str, font_size: PxScale) -> Rect {
-        let glyphs = text::text_to_glyphs(text, &self.font, font_size);
+        let glyphs = self.glyph_cache().text_to_glyphs(text, &self.font, font_size);
         Rect { width: glyphs.width + self.word_margin, height: glyphs.height + self.word_margin }
     }
 
@@ -205,73 +635,86 @@ impl WordCloud {
         scale: f32,
         color_func: fn(&Word, &mut WyRand) -> Rgba<u8>
     ) -> RgbaImage {
-        let words = self.tokenizer.get_normalized_word_frequencies(text);
-
-        let (mut summed_area_table, mut gray_buffer) = match size {
-            WordCloudSize::FromDimensions { width, height } => {
-                let buf = GrayImage::from_pixel(width, height, Luma([0]));
-                let mut summed_area_table = vec![0; buf.len()];
+        let layout = self.generate_layout(text, size);
+        self.render_layout(&layout, scale, color_func)
+    }
 
-                u8_to_u32_vec(&buf, &mut summed_area_table);
-                (summed_area_table, buf)
-            },
-            WordCloudSize::FromMask(image) => {
-                let mut table = vec![0; image.len()];
-
-                u8_to_u32_vec(&image, &mut table);
-                sat::to_summed_area_table(
-                    &mut table, image.width() as usize, 0
-                );
-                (table, image)
-            }
-        };
+    /// Like [`WordCloud::generate_from_text_with_color_func`], but also returns the layout and
+    /// reports which words made it onto the canvas
+    pub fn generate_from_text_with_report(
+        &self,
+        text: &str,
+        size: WordCloudSize,
+        scale: f32,
+        color_func: fn(&Word, &mut WyRand) -> Rgba<u8>
+    ) -> (RgbaImage, Layout, PlacementReport) {
+        let start = Instant::now();
+        let layout = self.generate_layout(text, size);
+        let layout_time = start.elapsed();
 
-        #[cfg(feature = "visualize")]
-        {
-            let mask = if matches!(WordCloudSize::FromMask, _size) {
-                Some(gray_buffer.to_vec())
-            }
-            else {
-                None
-            };
+        let start = Instant::now();
+        let image = self.render_layout(&layout, scale, color_func);
+        let render_time = start.elapsed();
 
-            let serialized = serde_json::to_string(&Message::InitMessage(Init {
-                width: gray_buffer.width(),
-                height: gray_buffer.height(),
-                mask,
-                font: self.font.as_slice().to_vec(),
-                background_color: self.background_color.0,
-            })).unwrap();
-            println!("{}", serialized);
-        };
+        let report = PlacementReport::new(&layout, layout_time, render_time);
+        (image, layout, report)
+    }
 
-        let mut final_words = Vec::with_capacity(words.len());
+    /// Places the words of the text without drawing them, see [`WordCloud::render_layout`]
+    pub fn generate_layout(&self, text: &str, size: WordCloudSize) -> Layout {
+        let mut rng = self.rng();
+        self.layout_words(text, size, &mut rng)
+    }
 
-        let mut last_freq = 1.0;
+    /// Draws a layout created by [`WordCloud::generate_layout`]
+    ///
+    /// Colors are picked with their own random number generator, so with a seed, the image and
+    /// the last frames of [`WordCloud::render_placement`] and [`WordCloud::render_transition`]
+    /// are the same as what [`WordCloud::generate_from_text`] draws.
+    pub fn render_layout(&self, layout: &Layout, scale: f32, color_func: fn(&Word, &mut WyRand) -> Rgba<u8>) -> RgbaImage {
+        let mut rng = self.rng();
+        self.render(layout, scale, color_func, &mut rng)
+    }
 
-        let mut rng = match self.rng_seed {
+    fn rng(&self) -> WyRand {
+        match self.rng_seed {
             Some(seed) => WyRand::new_seed(seed),
             None => WyRand::new(),
+        }
+    }
+
+    fn layout_words(&self, text: &str, size: WordCloudSize, rng: &mut WyRand) -> Layout {
+        let words = self.tokenizer.word_weights(text);
+        let (width, height, mask) = match size {
+            WordCloudSize::FromDimensions { width, height } => (width, height, None),
+            WordCloudSize::FromMask(image) => (image.width(), image.height(), Some(image)),
         };
 
+        self.notify(|observer| observer.init(width, height, mask.as_ref(), &self.font, self.background_color));
+
+        let mask_spans = mask.as_ref().map(create_mask_spans);
+        let mut occupancy = Occupancy::new(self.occupancy_backend, width, height, mask);
+        for region in &self.reserved_regions {
+            region.mark(width, height, |x, y| occupancy.mark(x, y));
+        }
+        occupancy.update();
+        let usable_pixels = occupancy.free_pixels();
+
         let first_word = words.first()
             .expect("There are no words!");
 
-        let skip_list = create_mask_skip_list(&gray_buffer);
-
-        let mut font_size = {
+        let start_font_size = {
             let rect_at_image_height = self.text_dimensions_at_font_size(
-                first_word.0,
-                PxScale::from(gray_buffer.height() as f32 * 0.95)
+                &first_word.0,
+                PxScale::from(height as f32 * 0.95)
             );
 
             let height_ratio = rect_at_image_height.height as f32 / rect_at_image_height.width as f32;
 
-            let mut start_height = gray_buffer.width() as f32 * height_ratio;
+            let mut start_height = width as f32 * height_ratio;
 
-            if matches!(WordCloudSize::FromMask, _size) {
-                let black_pixels = gray_buffer.as_raw().iter().filter(|p| **p == 0).count();
-                let available_space: f32 = black_pixels as f32 / gray_buffer.len() as f32;
+            if mask_spans.is_some() || !self.reserved_regions.is_empty() {
+                let available_space: f32 = usable_pixels as f32 / (width as f32 * height as f32);
                 start_height *= available_space;
             }
 
@@ -283,143 +726,462 @@ impl WordCloud {
             }
         };
 
-        'outer: for (word, freq) in &words {
-            if !self.tokenizer.repeat && self.relative_font_scaling != 0.0 {
-                font_size *= self.relative_font_scaling * (freq / last_freq) + (1.0 - self.relative_font_scaling);
-            }
+        let canvas = Canvas { occupancy, mask_spans, usable_pixels, observed: true };
 
-            if font_size < self.min_font_size {
-                break;
-            }
+        match self.required_words {
+            Some(required_words) => self.fit_required_words(&words, required_words, &canvas, start_font_size, rng),
+            None => self.place_words(&words, canvas, start_font_size, rng),
+        }
+    }
 
-            let initial_font_size = font_size;
+    /// Lays out the words again with a smaller starting font size until the heaviest
+    /// `required_words` all fit
+    ///
+    /// Bisects between the estimated starting size and the minimum font size, keeping the largest
+    /// starting size that fits every required word. Each attempt starts from the same random state.
+    /// Observers only hear about the attempt that is kept.
+    fn fit_required_words(
+        &self,
+        words: &[(Cow<str>, f32)],
+        required_words: usize,
+        canvas: &Canvas,
+        start_font_size: f32,
+        rng: &mut WyRand,
+    ) -> Layout {
+        let required = &words[..required_words.min(words.len())];
+        let fits = |layout: &Layout| layout.dropped_words.iter()
+            .all(|dropped| !required.iter().any(|(word, _)| *word == dropped.text));
+
+        let initial_rng = rng.clone();
+        let attempt = |font_size: f32, observed: bool, rng: &mut WyRand| {
+            *rng = initial_rng.clone();
+            self.place_words(words, Canvas { observed, ..canvas.clone() }, font_size, rng)
+        };
 
-            let mut should_rotate = rng.generate::<u8>() <= (255.0 * self.word_rotate_chance) as u8;
-            let mut tried_rotate = false;
-            let mut glyphs;
+        let mut kept = (start_font_size, attempt(start_font_size, false, rng));
+        if !fits(&kept.1) {
+            let mut best = None;
+            let mut low = self.min_font_size.min(start_font_size);
+            let mut high = start_font_size;
 
-            let has_mask = matches!(WordCloudSize::FromMask, _size);
+            for _ in 0..FIT_ATTEMPTS {
+                if high - low < self.font_step.max(0.5) {
+                    break;
+                }
 
-            let pos = loop {
-                glyphs = text::text_to_glyphs(word, &self.font, PxScale::from(font_size));
-                let glyphs_height = self.glyphs_height(&glyphs.glyphs);
+                let mid = (low + high) / 2.0;
+                let layout = attempt(mid, false, rng);
 
-                let rect = if !should_rotate {
-                    Rect { width: glyphs.width + self.word_margin, height: glyphs.height + self.word_margin }
+                if fits(&layout) {
+                    low = mid;
+                    best = Some((mid, layout, rng.clone()));
                 }
                 else {
-                    Rect { width: glyphs.height + self.word_margin, height: glyphs.width + self.word_margin }
-                };
+                    high = mid;
+                }
+            }
+
+            kept = match best {
+                Some((font_size, layout, best_rng)) => {
+                    *rng = best_rng;
+                    (font_size, layout)
+                },
+                // Nothing fit, so give the words as much room as possible
+                None => (low, attempt(low, false, rng)),
+            };
+        }
+
+        if !canvas.observed || self.observers().is_empty() {
+            return kept.1;
+        }
+
+        // Every attempt starts from the same random state, so this places the words the same way
+        // again for the observers to see
+        attempt(kept.0, true, rng)
+    }
 
-                #[cfg(feature = "visualize")]
-                {
-                    let serialized = serde_json::to_string(&Message::ChangeWordMessage(visualize::Word {
-                        text: word.to_string(),
-                        font_size: font_size as u32,
-                        rect_width: rect.width,
-                        rect_height: rect.height,
-                        rotation: if should_rotate { 270 } else { 0 },
-                    })).unwrap();
-                    println!("{}", serialized);
+    /// Finds the largest font size at or below `font_size` that the word has space for,
+    /// stepping down by the font step
+    fn search_font_size(&self, word: &str, mut font_size: f32, rotated: bool, canvas: &Canvas, rng: &mut WyRand) -> Result<(Point, f32, GlyphData), DropReason> {
+        match self.font_search {
+            FontSearch::Linear => loop {
+                let reason = match self.try_font_size(word, font_size, rotated, canvas, rng) {
+                    Ok((pos, glyphs)) => return Ok((pos, font_size, glyphs)),
+                    Err(reason) => reason,
                 };
 
-                if rect.width > gray_buffer.width() || rect.height > gray_buffer.height() {
-                    if Self::check_font_size(&mut font_size, self.font_step, self.min_font_size) {
-                        continue;
-                    }
-                    else {
-                        break 'outer;
-                    };
+                if !Self::check_font_size(&mut font_size, self.font_step, self.min_font_size) {
+                    return Err(reason);
                 }
+            },
+            FontSearch::Bisect => {
+                let reason = match self.try_font_size(word, font_size, rotated, canvas, rng) {
+                    Ok((pos, glyphs)) => return Ok((pos, font_size, glyphs)),
+                    Err(reason) => reason,
+                };
 
-                if has_mask {
-                    match sat::find_space_for_rect_masked(&summed_area_table, gray_buffer.width(), gray_buffer.height(), &skip_list, &rect, &mut rng) {
-                        Some(pos) => {
-                            let half_margin = self.word_margin as f32 / 2.0;
-                            let x = pos.x as f32 + half_margin;
-                            let y = pos.y as f32 + half_margin;
+                // Stay on the same sizes the linear search would try
+                let mut steps = 0;
+                let mut smallest_size = font_size;
+                while Self::check_font_size(&mut smallest_size, self.font_step, self.min_font_size) {
+                    steps += 1;
+                }
+                let size_at = |step: usize| font_size - step as f32 * self.font_step;
 
-                            break point(x, y)
-                        },
-                        None => {
-                            if !Self::check_font_size(&mut font_size, self.font_step, self.min_font_size) {
-                                if !tried_rotate {
-                                    should_rotate = true;
-                                    tried_rotate = true;
-                                    font_size = initial_font_size;
-                                }
-                                else {
-                                    break 'outer;
-                                }
-                            }
-                        }
-                    };
+                if steps == 0 {
+                    return Err(reason);
                 }
-                else {
-                    match sat::find_space_for_rect(&summed_area_table, gray_buffer.width(), gray_buffer.height(), &rect, &mut rng) {
-                        Some(pos) => {
-                            let half_margin = self.word_margin as f32 / 2.0;
-                            let x = pos.x as f32 + half_margin;
-                            let y = pos.y as f32 + half_margin;
 
-                            break point(x, y)
+                // If the word doesn't fit at the smallest size, it won't fit at any of them
+                let mut best = match self.try_font_size(word, smallest_size, rotated, canvas, rng) {
+                    Ok((pos, glyphs)) => (pos, smallest_size, glyphs),
+                    Err(reason) => return Err(reason),
+                };
+
+                let (mut low, mut high) = (1, steps);
+                while low < high {
+                    let mid = (low + high) / 2;
+
+                    match self.try_font_size(word, size_at(mid), rotated, canvas, rng) {
+                        Ok((pos, glyphs)) => {
+                            best = (pos, size_at(mid), glyphs);
+                            high = mid;
                         },
-                        None => {
-                            if !Self::check_font_size(&mut font_size, self.font_step, self.min_font_size) {
-                                if !tried_rotate {
-                                    should_rotate = true;
-                                    tried_rotate = true;
-                                    font_size = initial_font_size;
-                                }
-                                else {
-                                    break 'outer;
-                                }
-                            }
-                        }
-                    };
+                        Err(_) => low = mid + 1,
+                    }
                 }
 
+                Ok(best)
+            },
+        }
+    }
+
+    /// Looks for a random free spot for the word at exactly this font size
+    fn try_font_size(&self, word: &str, font_size: f32, rotated: bool, canvas: &Canvas, rng: &mut WyRand) -> Result<(Point, GlyphData), DropReason> {
+        let glyphs = self.glyph_cache().text_to_glyphs(word, &self.font, PxScale::from(font_size));
+
+        let rect = if !rotated {
+            Rect { width: glyphs.width + self.word_margin, height: glyphs.height + self.word_margin }
+        }
+        else {
+            Rect { width: glyphs.height + self.word_margin, height: glyphs.width + self.word_margin }
+        };
+
+        if canvas.observed {
+            self.notify(|observer| observer.word_attempt(word, font_size, &rect, rotated));
+        }
+
+        if rect.width > canvas.occupancy.width() || rect.height > canvas.occupancy.height() {
+            return Err(DropReason::TooBig);
+        }
+
+        let (canvas_width, canvas_height) = (canvas.occupancy.width(), canvas.occupancy.height());
+        let mut reservoir = Reservoir::new(Some(&self.placement_bias), &rect, canvas_width, canvas_height, rng);
+
+        // Checks are the hot loop, so they're only reported when someone is listening, and the
+        // observers are only kept locked through the search then
+        let pos = if !canvas.observed || self.observers().is_empty() {
+            canvas.occupancy.find_space_for_rect(&rect, canvas.mask_spans.as_deref(), &mut reservoir, None)
+        }
+        else {
+            let mut observers = self.observers();
+            let mut report_check = |x: u32, y: u32, empty: bool| {
+                for observer in observers.iter_mut() {
+                    observer.rect_check(x, y, empty);
+                }
             };
-            text::draw_glyphs_to_gray_buffer(&mut gray_buffer, glyphs.clone(), &self.font, pos, should_rotate);
-
-            #[cfg(feature = "visualize")]
-            {
-                let serialized = serde_json::to_string(&Message::PlacedWordMessage(visualize::PlaceWord {
-                    text: word.to_string(),
-                    font_size: font_size as u32,
-                    x: pos.x as u32,
-                    y: pos.y as u32,
-                    rotation: if should_rotate { 270 } else { 0 },
-                })).unwrap();
-                println!("{}", serialized);
+
+            canvas.occupancy.find_space_for_rect(&rect, canvas.mask_spans.as_deref(), &mut reservoir, Some(&mut report_check))
+        };
+
+        match pos {
+            Some(pos) => {
+                let half_margin = self.word_margin as f32 / 2.0;
+                Ok((point(pos.x as f32 + half_margin, pos.y as f32 + half_margin), glyphs))
+            },
+            None => Err(DropReason::NoSpace),
+        }
+    }
+
+    /// Picks a rotation for the word and finds space for it, trying the other rotation if there
+    /// isn't any
+    fn find_placement(&self, word: &str, font_size: f32, canvas: &Canvas, rng: &mut WyRand) -> Result<(Point, f32, GlyphData, bool), DropReason> {
+        let mut should_rotate = rng.generate::<u8>() <= (255.0 * self.word_rotate_chance) as u8;
+
+        let mut placement = self.search_font_size(word, font_size, should_rotate, canvas, rng);
+        if matches!(placement, Err(DropReason::NoSpace)) {
+            should_rotate = true;
+            placement = self.search_font_size(word, font_size, should_rotate, canvas, rng);
+        }
+
+        placement.map(|(pos, size, glyphs)| (pos, size, glyphs, should_rotate))
+    }
+
+    /// Puts the word back where it was in the previous layout if there's space there, or else finds
+    /// a new place for it
+    fn find_stable_placement(&self, word: &str, font_size: f32, canvas: &Canvas, final_words: &[PlacedWord], rng: &mut WyRand) -> Result<(Point, f32, GlyphData, bool), DropReason> {
+        match self.find_previous_placement(word, font_size, canvas, final_words) {
+            Some(placement) => Ok(placement),
+            None => self.find_placement(word, font_size, canvas, rng),
+        }
+    }
+
+    /// Tries the word's new size at its old center, then its old size if that is smaller
+    fn find_previous_placement(&self, word: &str, font_size: f32, canvas: &Canvas, final_words: &[PlacedWord]) -> Option<(Point, f32, GlyphData, bool)> {
+        let previous_layout = self.previous_layout.as_ref()?;
+
+        // Repeated words are matched up in the order they were placed
+        let occurrence = final_words.iter().filter(|placed| placed.text == word).count();
+        let previous = previous_layout.words.iter()
+            .filter(|placed| placed.text == word)
+            .nth(occurrence)?;
+
+        let rect_size = |glyphs: &GlyphData| if !previous.rotated {
+            (glyphs.width, glyphs.height)
+        }
+        else {
+            (glyphs.height, glyphs.width)
+        };
+
+        let previous_glyphs = self.glyph_cache().text_to_glyphs(word, &self.font, PxScale::from(previous.font_size));
+        let (previous_width, previous_height) = rect_size(&previous_glyphs);
+
+        // The canvas may have changed size since
+        let (width, height) = (canvas.occupancy.width(), canvas.occupancy.height());
+        let center_x = (previous.position.x + previous_width as f32 / 2.0) * width as f32 / previous_layout.width as f32;
+        let center_y = (previous.position.y + previous_height as f32 / 2.0) * height as f32 / previous_layout.height as f32;
+
+        let half_margin = self.word_margin as f32 / 2.0;
+
+        for size in [font_size, previous.font_size.min(font_size)] {
+            if size < self.min_font_size {
+                break;
+            }
+
+            let glyphs = self.glyph_cache().text_to_glyphs(word, &self.font, PxScale::from(size));
+            let (glyphs_width, glyphs_height) = rect_size(&glyphs);
+            let rect = Rect { width: glyphs_width + self.word_margin, height: glyphs_height + self.word_margin };
+
+            let x = center_x - rect.width as f32 / 2.0;
+            let y = center_y - rect.height as f32 / 2.0;
+            if x >= 0.0 && y >= 0.0 && canvas.occupancy.rect_is_free(&rect, x as u32, y as u32) {
+                return Some((point(x.floor() + half_margin, y.floor() + half_margin), size, glyphs, previous.rotated));
+            }
+        }
+
+        None
+    }
+
+    /// Marks the word's pixels as taken and adds it to the layout
+    #[allow(clippy::too_many_arguments)]
+    fn commit_word(
+        &self,
+        canvas: &mut Canvas,
+        final_words: &mut Vec<PlacedWord>,
+        word: &str,
+        frequency: f32,
+        font_size: f32,
+        pos: Point,
+        glyphs: GlyphData,
+        rotated: bool,
+    ) {
+        let canvas_size = (canvas.occupancy.width(), canvas.occupancy.height());
+        text::mark_glyphs(canvas_size, glyphs, &self.font, &mut self.glyph_cache(), pos, rotated, |x, y| canvas.occupancy.mark(x, y));
+        canvas.occupancy.update();
+
+        let placed_word = PlacedWord {
+            text: word.to_string(),
+            font_size,
+            rotated,
+            position: pos,
+            frequency,
+        };
+
+        if canvas.observed {
+            self.notify(|observer| observer.word_placed(&placed_word));
+        }
+        final_words.push(placed_word);
+    }
+
+    /// Draws a pinned word where it was asked to go, unless it would stick out of the canvas there
+    fn pin_word(&self, canvas: &mut Canvas, final_words: &mut Vec<PlacedWord>, pinned: &PinnedWord, frequency: f32) -> Result<(), DropReason> {
+        let glyphs = self.glyph_cache().text_to_glyphs(&pinned.text, &self.font, PxScale::from(pinned.font_size));
+        let (width, height) = if !pinned.rotated {
+            (glyphs.width as f32, glyphs.height as f32)
+        }
+        else {
+            (glyphs.height as f32, glyphs.width as f32)
+        };
+
+        let x = (pinned.center.0 - width / 2.0).floor();
+        let y = (pinned.center.1 - height / 2.0).floor();
+
+        let fits = x >= 0.0 && y >= 0.0
+            && x + width <= canvas.occupancy.width() as f32
+            && y + height <= canvas.occupancy.height() as f32;
+        if !fits {
+            return Err(DropReason::OffCanvas);
+        }
+
+        self.commit_word(canvas, final_words, &pinned.text, frequency, pinned.font_size, point(x, y), glyphs, pinned.rotated);
+        Ok(())
+    }
+
+    /// Places copies of the words that were placed, heaviest first, until less than `fill` of the
+    /// usable space is free or nothing fits at the minimum font size anymore
+    fn fill_free_space(&self, canvas: &mut Canvas, final_words: &mut Vec<PlacedWord>, fill: f32, mut font_size: f32, rng: &mut WyRand) {
+        let sources: Vec<(String, f32)> = final_words.iter()
+            .filter(|word| !self.is_pinned(&word.text))
+            .map(|word| (word.text.clone(), word.frequency))
+            .collect();
+
+        // The last word may have stopped the layout by being too small
+        font_size = font_size.max(self.min_font_size);
+
+        let is_filled = |canvas: &Canvas| {
+            canvas.occupancy.free_pixels() as f32 <= canvas.usable_pixels as f32 * fill
+        };
+
+        loop {
+            let mut placed_any = false;
+
+            for (word, frequency) in &sources {
+                if is_filled(canvas) {
+                    return;
+                }
+
+                if let Ok((pos, size, glyphs, rotated)) = self.find_stable_placement(word, font_size, canvas, final_words, rng) {
+                    font_size = size;
+                    placed_any = true;
+                    self.commit_word(canvas, final_words, word, *frequency, font_size, pos, glyphs, rotated);
+                }
+            }
+
+            if !placed_any {
+                return;
+            }
+        }
+    }
+
+    fn drop_words(&self, canvas: &Canvas, dropped_words: &mut Vec<DroppedWord>, words: impl IntoIterator<Item = DroppedWord>) {
+        for word in words {
+            if canvas.observed {
+                self.notify(|observer| observer.word_dropped(&word));
+            }
+            dropped_words.push(word);
+        }
+    }
+
+    fn is_pinned(&self, word: &str) -> bool {
+        self.pinned_words.iter().any(|pinned| self.tokenizer.same_word(&pinned.text, word))
+    }
+
+    fn place_words(&self, words: &[(Cow<str>, f32)], mut canvas: Canvas, start_font_size: f32, rng: &mut WyRand) -> Layout {
+        let mut final_words: Vec<PlacedWord> = Vec::with_capacity(words.len());
+        let mut dropped_words = Vec::new();
+
+        for pinned in &self.pinned_words {
+            let frequency = words.iter()
+                .find(|(word, _)| self.tokenizer.same_word(word, &pinned.text))
+                .map_or(1.0, |(_, freq)| *freq);
+
+            if let Err(reason) = self.pin_word(&mut canvas, &mut final_words, pinned, frequency) {
+                self.drop_words(&canvas, &mut dropped_words, [DroppedWord { text: pinned.text.clone(), frequency, reason }]);
+            }
+        }
+        let pinned_count = final_words.len();
+
+        let mut last_freq = 1.0;
+        let mut font_size = start_font_size;
+
+        let first_word = &words[0];
+        let max_weight = first_word.1;
+        let min_weight = words.last().map_or(max_weight, |(_, freq)| *freq);
+        // Becomes the size of the first word that gets placed
+        let mut largest_font_size = font_size;
+
+        'outer: for (rank, (word, freq)) in words.iter().enumerate() {
+            if self.is_pinned(word) {
+                continue;
+            }
+
+            match self.font_size_mapping.position(*freq, rank, words.len(), min_weight, max_weight) {
+                Some(position) => {
+                    let target_size = self.min_font_size + (largest_font_size - self.min_font_size) * position;
+                    // Never grow back past a size that already failed to fit
+                    font_size = font_size.min(target_size);
+                },
+                None => {
+                    if !self.tokenizer.repeats_words() && self.relative_font_scaling != 0.0 {
+                        font_size *= self.relative_font_scaling * (freq / last_freq) + (1.0 - self.relative_font_scaling);
+                    }
+                }
+            }
+
+            if font_size < self.min_font_size {
+                // Every word after this one is lighter, so none of them would be large enough either
+                self.drop_words(&canvas, &mut dropped_words, words[rank..].iter().filter(|(word, _)| !self.is_pinned(word)).map(|(word, freq)| DroppedWord { text: word.to_string(), frequency: *freq, reason: DropReason::BelowMinFontSize }));
+                break;
+            }
+
+            let initial_font_size = font_size;
+
+            let (pos, glyphs, rotated) = match self.find_stable_placement(word, initial_font_size, &canvas, &final_words, rng) {
+                Ok((pos, size, glyphs, rotated)) => {
+                    font_size = size;
+                    (pos, glyphs, rotated)
+                },
+                Err(reason) => {
+                    self.drop_words(&canvas, &mut dropped_words, [DroppedWord { text: word.to_string(), frequency: *freq, reason }]);
+
+                    if self.skip_unplaceable_words {
+                        // Smaller words may still fit, so carry on from the size this word started at
+                        font_size = initial_font_size;
+                        last_freq = *freq;
+                        continue 'outer;
+                    }
+
+                    self.drop_words(&canvas, &mut dropped_words, words[rank + 1..].iter().filter(|(word, _)| !self.is_pinned(word)).map(|(word, freq)| DroppedWord { text: word.to_string(), frequency: *freq, reason: DropReason::NotAttempted }));
+                    break 'outer;
+                }
             };
 
-            final_words.push(Word {
-                text: word,
-                font: &self.font,
-                font_size: PxScale::from(font_size),
-                glyphs: glyphs.clone(),
-                rotated: should_rotate,
-                position: pos,
-                frequency: *freq,
-                index: final_words.len()
-            });
-
-            // TODO: Do a partial sat like the Python implementation
-            u8_to_u32_vec(&gray_buffer, &mut summed_area_table);
-            let start_row = (pos.y - 1.0).min(0.0) as usize;
-            sat::to_summed_area_table(&mut summed_area_table, gray_buffer.width() as usize, start_row);
+            if final_words.len() == pinned_count {
+                largest_font_size = font_size;
+            }
+
+            self.commit_word(&mut canvas, &mut final_words, word, *freq, font_size, pos, glyphs, rotated);
 
             last_freq = *freq;
         }
 
-        WordCloud::generate_from_word_positions(
-            &mut rng, gray_buffer.width(), gray_buffer.height(), final_words, scale, self.background_color, color_func
-        )
+        let placed_count = final_words.len();
+        if let Some(fill) = self.fill {
+            self.fill_free_space(&mut canvas, &mut final_words, fill, font_size, rng);
+        }
+        let fill_words = final_words.len() - placed_count;
+
+        let free_pixels = canvas.occupancy.free_pixels();
+        let coverage = if canvas.usable_pixels > 0 {
+            (canvas.usable_pixels - free_pixels) as f32 / canvas.usable_pixels as f32
+        }
+        else {
+            0.0
+        };
+
+        Layout {
+            width: canvas.occupancy.width(),
+            height: canvas.occupancy.height(),
+            words: final_words,
+            dropped_words,
+            coverage,
+            fill_words,
+        }
     }
 }
 
-fn random_color_rgba(_word: &Word, rng: &mut WyRand) -> Rgba<u8> {
+/// The color function used by [`WordCloud::generate_from_text`]
+pub fn random_color_rgba(_word: &Word, rng: &mut WyRand) -> Rgba<u8> {
     let hue: u8 = rng.generate_range(0..255);
     // TODO: Python uses 0.8 for the saturation but it looks too washed out when used here
     //   Maybe something to do with the linear stuff?
@@ -434,13 +1196,6 @@ fn random_color_rgba(_word: &Word, rng: &mut WyRand) -> Rgba<u8> {
     Rgba([raw[0], raw[1], raw[2], 1])
 }
 
-// TODO: This doesn't seem particularly efficient
-fn u8_to_u32_vec(buffer: &GrayImage, dst: &mut [u32]) {
-    for (i, el) in buffer.as_raw().iter().enumerate() {
-        dst[i] = *el as u32;
-    }
-}
-
 /// Crops the image to its boundaries
 ///
 /// Useful for making the search space smaller when looking for a space to place a word
@@ -481,14 +1236,250 @@ fn _find_image_bounds(img: &GrayImage) -> Region {
     Region { x: min_x, y: min_y, width, height }
 }
 
-/// Finds the outline of a mask on the x axis
+/// Splits every row of a mask into the spans of usable (black) pixels
 ///
-/// Useful for skipping white pixels that can't be used when looking for a space to place a word
-fn create_mask_skip_list(img: &GrayImage) -> Vec<(usize, usize)> {
-    img.rows().map(|mut row| {
-        let furthest_left = row.rposition(|p| p == &Luma::from([0])).unwrap_or(img.width() as usize);
-        let furthest_right = row.position(|p| p == &Luma::from([0])).unwrap_or(0);
+/// Useful for skipping white pixels that can't be used when looking for a space to place a word,
+/// including the ones between separate parts of the mask
+fn create_mask_spans(img: &GrayImage) -> Vec<Vec<Span>> {
+    img.rows().map(|row| {
+        let mut spans = Vec::new();
+        let mut start = None;
+
+        for (x, p) in row.enumerate() {
+            match (p == &Luma::from([0]), start) {
+                (true, None) => start = Some(x as u32),
+                (false, Some(span_start)) => {
+                    spans.push(Span { start: span_start, end: x as u32 });
+                    start = None;
+                },
+                _ => {},
+            }
+        }
 
-        (furthest_right, furthest_left)
+        if let Some(start) = start {
+            spans.push(Span { start, end: img.width() });
+        }
+
+        spans
     }).collect()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// A seeded word cloud that keeps every word of the text
+    pub(crate) fn wordcloud(seed: u64) -> WordCloud {
+        WordCloud::default()
+            .with_tokenizer(Tokenizer::default().with_filter(HashSet::new()))
+            .with_rng_seed(seed)
+    }
+
+    pub(crate) fn size(width: u32, height: u32) -> WordCloudSize {
+        WordCloudSize::FromDimensions { width, height }
+    }
+
+    #[test]
+    fn skips_words_that_dont_fit() {
+        let text = "aa aa aa aa abcdefghijklmnopqrstuvwxyz abcdefghijklmnopqrstuvwxyz abcdefghijklmnopqrstuvwxyz cc cc";
+        let wordcloud = wordcloud(1).with_min_font_size(20.0);
+
+        let layout = wordcloud.generate_layout(text, size(200, 200));
+        assert_eq!(layout.words.len(), 1);
+        assert_eq!(layout.dropped_words.len(), 2);
+        assert_eq!(layout.dropped_words[1].reason, DropReason::NotAttempted);
+
+        let layout = wordcloud
+            .with_skip_unplaceable_words(true)
+            .generate_layout(text, size(200, 200));
+        let placed: Vec<&str> = layout.words.iter().map(|word| word.text.as_str()).collect();
+        let dropped: Vec<&str> = layout.dropped_words.iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(placed, vec!["aa", "cc"]);
+        assert_eq!(dropped, vec!["abcdefghijklmnopqrstuvwxyz"]);
+    }
+
+    #[test]
+    fn required_words_all_fit() {
+        let text = "alpha alpha alpha alpha alpha bravo bravo bravo bravo charlie charlie charlie delta delta echo";
+        let wordcloud = wordcloud(3)
+            .with_min_font_size(40.0)
+            .with_relative_font_scaling(0.0)
+            .with_word_rotate_chance(0.0);
+
+        let layout = wordcloud.generate_layout(text, size(200, 200));
+        assert!(!layout.dropped_words.is_empty());
+
+        let layout = wordcloud
+            .with_required_words(Some(5))
+            .generate_layout(text, size(200, 200));
+        assert_eq!(layout.words.len(), 5);
+        assert!(layout.dropped_words.is_empty());
+    }
+
+    #[test]
+    fn bisect_finds_same_size_as_linear() {
+        let canvas = Canvas {
+            occupancy: Occupancy::new(OccupancyBackend::SummedAreaTable, 120, 40, None),
+            mask_spans: None,
+            usable_pixels: 120 * 40,
+            observed: true,
+        };
+
+        let mut rng = WyRand::new_seed(1);
+        let linear = WordCloud::default()
+            .search_font_size("hello", 80.0, false, &canvas, &mut rng)
+            .unwrap();
+        let bisect = WordCloud::default()
+            .with_font_search(FontSearch::Bisect)
+            .search_font_size("hello", 80.0, false, &canvas, &mut rng)
+            .unwrap();
+
+        assert!(linear.1 < 80.0);
+        assert_eq!(linear.1, bisect.1);
+        assert_eq!(WordCloud::default().with_min_font_size(30.0).search_font_size("hello", 80.0, true, &canvas, &mut rng).err(), Some(DropReason::TooBig));
+    }
+
+    #[test]
+    fn mask_spans_keep_holes() {
+        let mut mask = GrayImage::from_pixel(10, 2, Luma([255]));
+        for x in (1..4).chain(6..10) {
+            mask.put_pixel(x, 0, Luma([0]));
+        }
+
+        let spans = create_mask_spans(&mask);
+        assert_eq!(spans[0], vec![Span { start: 1, end: 4 }, Span { start: 6, end: 10 }]);
+        assert!(spans[1].is_empty());
+    }
+
+    #[test]
+    fn span_search_matches_brute_force() {
+        // Two islands of free space, the second one running into the right and bottom edges
+        let (width, height) = (120, 80);
+        let mut mask = GrayImage::from_pixel(width, height, Luma([255]));
+        for y in 0..height {
+            for x in 0..width {
+                if (10..50).contains(&x) && (10..40).contains(&y) || x >= 70 && y >= 30 {
+                    mask.put_pixel(x, y, Luma([0]));
+                }
+            }
+        }
+
+        let spans = create_mask_spans(&mask);
+        let mut table: Vec<u32> = mask.as_raw().iter().map(|p| (*p != 0) as u32).collect();
+        sat::to_summed_area_table(&mut table, width as usize, 0);
+
+        // The largest rect only just fits the second island, against both edges
+        for rect in [Rect { width: 1, height: 1 }, Rect { width: 15, height: 8 }, Rect { width: 40, height: 30 }, Rect { width: 50, height: 50 }] {
+            let brute_force: Vec<(u32, u32)> = (0..height - rect.height)
+                .flat_map(|y| (0..width - rect.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| sat::region_is_empty(&table, width as usize, x as usize, y as usize, rect.width as usize, rect.height as usize))
+                .collect();
+
+            let mut found = Vec::new();
+            let mut rng = WyRand::new_seed(1);
+            let mut reservoir = Reservoir::new(None, &rect, width, height, &mut rng);
+            let mut on_check = |x, y, empty| if empty { found.push((x, y)) };
+            sat::search_for_rect(&table, width, height, Some(&spans), &rect, &mut reservoir, Some(&mut on_check));
+
+            assert!(!brute_force.is_empty());
+            assert_eq!(found, brute_force, "{}x{}", rect.width, rect.height);
+        }
+    }
+
+    #[test]
+    fn fill_repeats_words_until_target() {
+        let text = "rust rust rust cargo cargo crate";
+        let layout = wordcloud(1).generate_layout(text, size(200, 150));
+        assert_eq!(layout.words.len(), 3);
+
+        let filled = wordcloud(1).with_fill(Some(0.6)).generate_layout(text, size(200, 150));
+        assert!(filled.words.len() > 3);
+        assert!(filled.coverage >= 0.4);
+        assert_eq!(filled.fill_words, filled.words.len() - 3);
+        assert!(filled.words.iter().all(|word| ["rust", "cargo", "crate"].contains(&word.text.as_str())));
+    }
+
+    #[test]
+    fn reserved_regions_stay_empty() {
+        let text = "rust rust rust cargo cargo crate clippy rustc borrow trait";
+        let image = wordcloud(1)
+            .with_reserved_region(ReservedRegion::Rect(Region { x: 50, y: 40, width: 100, height: 70 }))
+            .with_reserved_region("polygon:0,0;40,0;0,40".parse().unwrap())
+            .generate_from_text(text, size(200, 150), 1.0);
+        assert!(image.enumerate_pixels().any(|(_, _, p)| p.0 != [0, 0, 0, 255]));
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if (50..150).contains(&x) && (40..110).contains(&y) || x + y < 38 {
+                assert_eq!(pixel.0, [0, 0, 0, 255], "({}, {}) should be empty", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn pinned_words_are_placed_first() {
+        let text = "rust rust rust cargo cargo crate clippy rustc";
+        let layout = wordcloud(1)
+            .with_pinned_word("wcloud@150,100,40".parse().unwrap())
+            .with_pinned_word("Cargo@20,100,20,rotated".parse().unwrap())
+            .with_pinned_word("logo@290,10,40".parse().unwrap())
+            .generate_layout(text, size(300, 200));
+
+        let pinned = &layout.words[0];
+        assert_eq!((pinned.text.as_str(), pinned.font_size, pinned.rotated), ("wcloud", 40.0, false));
+        assert!((pinned.position.x - 150.0).abs() < 80.0 && pinned.position.y < 100.0);
+        assert!(layout.words.iter().any(|word| word.text == "rust"));
+
+        // Pins match the words of the text whatever their case
+        assert!(layout.words[1].rotated);
+        assert!(layout.words[1].frequency < 1.0);
+        assert_eq!(layout.words.iter().filter(|word| word.text.eq_ignore_ascii_case("cargo")).count(), 1);
+
+        assert_eq!(layout.dropped_words[0].text, "logo");
+        assert_eq!(layout.dropped_words[0].reason, DropReason::OffCanvas);
+
+        assert!("wcloud@1,2".parse::<PinnedWord>().is_err());
+        assert!("wcloud@1,2,3,sideways".parse::<PinnedWord>().is_err());
+    }
+
+    #[test]
+    fn previous_layout_keeps_words_in_place() {
+        let text = "rust rust rust rust cargo cargo cargo crate crate clippy rustc borrow trait macro";
+        let first = wordcloud(1).generate_layout(text, size(300, 200));
+        let saved: Layout = first.to_string().parse().unwrap();
+        assert_eq!(saved.words, first.words);
+
+        let second = wordcloud(2)
+            .with_previous_layout(Some(saved))
+            .generate_layout(&format!("{} lifetime lifetime", text), size(300, 200));
+
+        for word in &first.words {
+            let moved = second.words.iter().find(|placed| placed.text == word.text).unwrap();
+            if moved.font_size == word.font_size {
+                assert_eq!(moved.position, word.position, "{} moved", word.text);
+            }
+        }
+        assert!(second.words.iter().any(|placed| placed.text == "lifetime"));
+
+        assert!("300".parse::<Layout>().is_err());
+        assert!("300 200\n1 2 three false 1 rust".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn placement_frames_add_words_in_order() {
+        let text = "rust rust rust cargo cargo crate clippy rustc borrow";
+        let wordcloud = wordcloud(1);
+        let layout = wordcloud.generate_layout(text, size(200, 150));
+        let frames = wordcloud.render_placement(&layout, 2, 1.0, random_color_rgba);
+
+        assert_eq!(frames.len(), 1 + layout.words.len().div_ceil(2));
+        assert!(frames[0].pixels().all(|p| p.0 == [0, 0, 0, 255]));
+        assert_eq!(frames.last(), Some(&wordcloud.generate_from_text(text, size(200, 150), 1.0)));
+
+        let transition = wordcloud.render_transition(&layout, &layout, 2, 1.0, random_color_rgba);
+        assert_eq!(transition.last(), frames.last());
+
+        let drawn = |frame: &RgbaImage| frame.pixels().filter(|p| p.0 != [0, 0, 0, 255]).count();
+        assert!(frames.windows(2).all(|pair| drawn(&pair[0]) < drawn(&pair[1])));
+    }
+}
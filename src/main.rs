@@ -1,9 +1,9 @@
 use std::io::{self, Read, stdout};
-use wcloud::{Tokenizer, WordCloud, WordCloudSize, DEFAULT_EXCLUDE_WORDS_TEXT};
+use wcloud::{random_color_rgba, Tokenizer, Tokenize, TokenMode, CaseStrategy, Language, WordCloud, WordCloudSize, FontSizeMapping, FontSearch, OccupancyBackend, PlacementBias, ReservedRegion, PinnedWord, Layout, DropReason, AnimationFormat, save_animation, parse_word_list, DEFAULT_EXCLUDE_WORDS_TEXT};
 use clap::{Arg, App};
 use regex::Regex;
 use std::fs;
-use std::collections::HashSet;
+use std::time::Duration;
 use image::codecs::png::PngEncoder;
 use image::{ImageEncoder, ColorType, Rgba};
 use ab_glyph::FontVec;
@@ -13,7 +13,7 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
 
-    let matches = App::new("wcloud")
+    let app = App::new("wcloud")
         .version(VERSION)
         .author("isaackd <afrmtbl@gmail.com>")
         .about("Generate word clouds!")
@@ -25,6 +25,37 @@ fn main() {
             .long("regex")
             .value_name("REGEX")
             .help("Sets a custom regex to tokenize words with"))
+        .arg(Arg::with_name("case")
+            .long("case")
+            .value_name("STRATEGY")
+            .help("Sets how differently cased words are counted and shown (most-common, sensitive, lower, upper, title) [most-common]"))
+        .arg(Arg::with_name("identifiers")
+            .long("identifiers")
+            .help("Splits camelCase, PascalCase, snake_case and kebab-case identifiers into their component words"))
+        .arg(Arg::with_name("exclude-keywords")
+            .long("exclude-keywords")
+            .value_name("LANGUAGES")
+            .help("A comma-separated list of languages whose keywords are excluded (c, cpp, go, java, javascript, python, rust, typescript)"))
+        .arg(Arg::with_name("social")
+            .long("social")
+            .conflicts_with("regex")
+            .help("Keeps #hashtags and @mentions as single words and counts emoji as words, replacing the tokenization regex"))
+        .arg(Arg::with_name("hashtags-only")
+            .long("hashtags-only")
+            .conflicts_with("regex")
+            .help("Only counts #hashtags, implies --social"))
+        .arg(Arg::with_name("strip-html")
+            .long("strip-html")
+            .help("Removes HTML/XML tags and entities before tokenizing"))
+        .arg(Arg::with_name("strip-markdown")
+            .long("strip-markdown")
+            .help("Removes Markdown syntax such as code fences and link destinations before tokenizing"))
+        .arg(Arg::with_name("strip-urls")
+            .long("strip-urls")
+            .help("Removes URLs before tokenizing"))
+        .arg(Arg::with_name("strip-emails")
+            .long("strip-emails")
+            .help("Removes email addresses before tokenizing"))
         .arg(Arg::with_name("width")
             .long("width")
             .value_name("NUM")
@@ -75,6 +106,76 @@ fn main() {
             .long("relative-scaling")
             .value_name("NUM")
             .help("Sets how much of an impact word frequency has on the font size of the word (0.0 - 1.0) [0.5]"))
+        .arg(Arg::with_name("size-mapping")
+            .long("size-mapping")
+            .value_name("MAPPING")
+            .help("Sets how word frequency maps to font size (relative, linear, sqrt, log, rank, tiers, tiers:N) [relative]"))
+        .arg(Arg::with_name("skip-unplaceable")
+            .long("skip-unplaceable")
+            .help("Keeps placing smaller words after a word doesn't fit, and lists the words that were left out"))
+        .arg(Arg::with_name("fill")
+            .long("fill")
+            .value_name("NUM")
+            .help("Keeps placing copies of the most frequent words until no more than this fraction of the space is free (0.0 - 1.0)"))
+        .arg(Arg::with_name("required-words")
+            .long("required-words")
+            .value_name("NUM")
+            .help("Shrinks the word cloud until this many of the most frequent words all fit"))
+        .arg(Arg::with_name("report")
+            .long("report")
+            .value_name("FILE")
+            .help("Writes a JSON report of the placed and dropped words, the covered area and timing to this file"))
+        .arg(Arg::with_name("font-search")
+            .long("font-search")
+            .value_name("SEARCH")
+            .help("Sets how smaller font sizes are searched when a word doesn't fit (linear, bisect) [linear]"))
+        .arg(Arg::with_name("occupancy")
+            .long("occupancy")
+            .value_name("BACKEND")
+            .help("Sets how free space is tracked, pyramid is faster and bitmap uses much less memory for large canvases (sat, pyramid, bitmap) [sat]"))
+        .arg(Arg::with_name("bias")
+            .long("bias")
+            .value_name("BIAS")
+            .help("Makes words more likely to be placed in some spots (uniform, center, center:SPREAD) [uniform]"))
+        .arg(Arg::with_name("density-map")
+            .long("density-map")
+            .value_name("FILE")
+            .help("Places words more often where this grayscale image is brighter, and never where it is black"))
+        .arg(Arg::with_name("reserve")
+            .long("reserve")
+            .value_name("REGION")
+            .multiple_occurrences(true)
+            .help("Keeps words out of an area, such as where a logo will go. Can be given more than once (rect:X,Y,WIDTH,HEIGHT or polygon:X1,Y1;X2,Y2;...)"))
+        .arg(Arg::with_name("pin")
+            .long("pin")
+            .value_name("WORD@X,Y,SIZE")
+            .multiple_occurrences(true)
+            .help("Draws a word centered at a fixed position and size before placing the others, add ,rotated to rotate it. Can be given more than once"))
+        .arg(Arg::with_name("previous-layout")
+            .long("previous-layout")
+            .value_name("FILE")
+            .help("Keeps words where they were in a layout saved with --save-layout, if there's still space for them"))
+        .arg(Arg::with_name("save-layout")
+            .long("save-layout")
+            .value_name("FILE")
+            .help("Saves where each word was placed, to be used with --previous-layout next time"))
+        .arg(Arg::with_name("transition")
+            .long("transition")
+            .value_name("FILE")
+            .requires("previous-layout")
+            .help("Saves an animation of the words moving from the previous layout to the new one (.gif or .png)"))
+        .arg(Arg::with_name("transition-frames")
+            .long("transition-frames")
+            .value_name("NUM")
+            .help("Sets how many frames the transition animation has [30]"))
+        .arg(Arg::with_name("animate")
+            .long("animate")
+            .value_name("FILE")
+            .help("Saves an animation of the words appearing in the order they were placed (.gif or .png)"))
+        .arg(Arg::with_name("words-per-frame")
+            .long("words-per-frame")
+            .value_name("NUM")
+            .help("Sets how many words appear in each frame of the placement animation [1]"))
         .arg(Arg::with_name("mask")
             .long("mask")
             .value_name("FILE")
@@ -82,7 +183,27 @@ fn main() {
         .arg(Arg::with_name("exclude-words")
             .long("exclude-words")
             .value_name("FILE")
-            .help("A newline-separated list of words to exclude from the word cloud"))
+            .help("A newline-separated list of words to exclude from the word cloud. Lines can also be globs (uh*) or regexes wrapped in slashes (/^uh+$/)"))
+        .arg(Arg::with_name("min-frequency")
+            .long("min-frequency")
+            .value_name("NUM")
+            .help("Excludes words that occur fewer times than this"))
+        .arg(Arg::with_name("max-frequency")
+            .long("max-frequency")
+            .value_name("NUM")
+            .help("Excludes words that occur more times than this"))
+        .arg(Arg::with_name("max-document-share")
+            .long("max-document-share")
+            .value_name("NUM")
+            .help("Excludes words that appear in more than this fraction of lines (0.0 - 1.0)"))
+        .arg(Arg::with_name("include-words")
+            .long("include-words")
+            .value_name("FILE")
+            .help("A newline-separated list of the only words to count. Lines wrapped in slashes (/regex/) are regexes"))
+        .arg(Arg::with_name("aliases")
+            .long("aliases")
+            .value_name("FILE")
+            .help("A file of aliases that merge word variants, one 'Canonical: variant, variant' mapping per line"))
         .arg(Arg::with_name("output")
             .long("output")
             .short('o')
@@ -92,8 +213,19 @@ fn main() {
             .long("font")
             .short('f')
             .value_name("FILE")
-            .help("Sets the font used for the word cloud"))
-        .get_matches();
+            .help("Sets the font used for the word cloud"));
+
+    #[cfg(feature = "visualize")]
+    let app = app.arg(Arg::with_name("visualize")
+        .long("visualize")
+        .value_name("FILE")
+        .help("Writes every placement step to this file as JSON lines, for the placement visualizer"))
+        .arg(Arg::with_name("visualize-font")
+            .long("visualize-font")
+            .requires("visualize")
+            .help("Includes the font in the visualization file"));
+
+    let matches = app.get_matches();
 
     let mut tokenizer = Tokenizer::default();
 
@@ -120,6 +252,46 @@ fn main() {
         tokenizer = tokenizer.with_regex(regex);
     }
 
+    if let Some(case_strategy) = matches.value_of("case") {
+        let case_strategy = case_strategy.parse::<CaseStrategy>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        tokenizer = tokenizer.with_case_strategy(case_strategy);
+    }
+
+    if matches.is_present("identifiers") {
+        tokenizer = tokenizer.with_mode(TokenMode::Identifiers);
+    }
+
+    if matches.is_present("social") {
+        tokenizer = tokenizer.with_mode(TokenMode::Social);
+    }
+
+    if matches.is_present("hashtags-only") {
+        tokenizer = tokenizer.with_hashtags_only(true);
+    }
+
+    if let Some(languages) = matches.value_of("exclude-keywords") {
+        let languages = languages.split(',')
+            .map(|language| language.trim().parse::<Language>())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        tokenizer = tokenizer.with_keywords_excluded(&languages);
+    }
+
+    tokenizer = tokenizer
+        .with_strip_html(matches.is_present("strip-html"))
+        .with_strip_markdown(matches.is_present("strip-markdown"))
+        .with_strip_urls(matches.is_present("strip-urls"))
+        .with_strip_emails(matches.is_present("strip-emails"));
+
     let exclude_words = if let Some(exclude_words_path) = matches.value_of("exclude-words") {
         fs::read_to_string(exclude_words_path)
             .unwrap_or_else(|_| panic!("Unable to read exclude words file \'{}\'", exclude_words_path))
@@ -131,8 +303,59 @@ fn main() {
     };
 
     if !exclude_words.is_empty() {
-        let exclude_words = exclude_words.lines().collect::<HashSet<_>>();
-        tokenizer = tokenizer.with_filter(exclude_words);
+        let (exclude_words, exclude_patterns) = match parse_word_list(&exclude_words) {
+            Ok(list) => list,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1)
+            }
+        };
+
+        tokenizer = tokenizer
+            .with_filter(exclude_words)
+            .with_filter_patterns(exclude_patterns);
+    }
+
+    if let Some(min_frequency) = matches.value_of("min-frequency") {
+        tokenizer = tokenizer.with_min_frequency(
+            min_frequency.parse()
+                .expect("The minimum frequency must be a valid number")
+        );
+    }
+
+    if let Some(max_frequency) = matches.value_of("max-frequency") {
+        tokenizer = tokenizer.with_max_frequency(
+            Some(max_frequency.parse()
+                .expect("The maximum frequency must be a valid number"))
+        );
+    }
+
+    if let Some(max_document_share) = matches.value_of("max-document-share") {
+        tokenizer = tokenizer.with_max_document_share(
+            Some(max_document_share.parse()
+                .expect("The maximum document share must be a number between 0 and 1"))
+        );
+    }
+
+    if let Some(include_words_path) = matches.value_of("include-words") {
+        let include_words = fs::read_to_string(include_words_path)
+            .unwrap_or_else(|_| panic!("Unable to read include words file \'{}\'", include_words_path));
+
+        let (words, patterns) = match parse_word_list(&include_words) {
+            Ok(list) => list,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1)
+            }
+        };
+
+        tokenizer = tokenizer
+            .with_include_words(words)
+            .with_include_patterns(patterns);
+    }
+
+    if let Some(aliases_path) = matches.value_of("aliases") {
+        tokenizer = tokenizer.with_aliases_from_path(aliases_path.into());
     }
 
     let wordcloud_size = match matches.value_of("mask") {
@@ -169,6 +392,28 @@ fn main() {
         }
     };
 
+    let text = if let Some(text_file_path) = matches.value_of("text") {
+        fs::read_to_string(text_file_path)
+            .unwrap_or_else(|_| panic!("Unable to read text file \'{}\'", text_file_path))
+    }
+    else {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)
+            .expect("Unable to read stdin");
+
+        buffer
+    };
+
+    if tokenizer.word_weights(&text).is_empty() {
+        if matches.is_present("hashtags-only") {
+            eprintln!("There are no #hashtags in the text");
+        }
+        else {
+            eprintln!("There are no words in the text");
+        }
+        std::process::exit(1)
+    }
+
     let mut wordcloud = WordCloud::default()
         .with_tokenizer(tokenizer)
         .with_background_color(background_color);
@@ -215,6 +460,133 @@ fn main() {
         );
     }
 
+    if let Some(mapping) = matches.value_of("size-mapping") {
+        let mapping = mapping.parse::<FontSizeMapping>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        wordcloud = wordcloud.with_font_size_mapping(mapping);
+    }
+
+    if let Some(search) = matches.value_of("font-search") {
+        let search = search.parse::<FontSearch>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        wordcloud = wordcloud.with_font_search(search);
+    }
+
+    if let Some(backend) = matches.value_of("occupancy") {
+        let backend = backend.parse::<OccupancyBackend>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        wordcloud = wordcloud.with_occupancy_backend(backend);
+    }
+
+    if let Some(bias) = matches.value_of("bias") {
+        let bias = bias.parse::<PlacementBias>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            });
+
+        wordcloud = wordcloud.with_placement_bias(bias);
+    }
+
+    if let Some(density_map_path) = matches.value_of("density-map") {
+        let density_map = image::open(density_map_path)
+            .expect("Unable to read density map file")
+            .to_luma8();
+
+        wordcloud = wordcloud.with_placement_bias(PlacementBias::Density(density_map));
+    }
+
+    if let Some(regions) = matches.values_of("reserve") {
+        for region in regions {
+            let region = region.parse::<ReservedRegion>()
+                .unwrap_or_else(|e| {
+                    println!("{}", e);
+                    std::process::exit(1)
+                });
+
+            wordcloud = wordcloud.with_reserved_region(region);
+        }
+    }
+
+    if let Some(pinned_words) = matches.values_of("pin") {
+        for pinned in pinned_words {
+            let pinned = pinned.parse::<PinnedWord>()
+                .unwrap_or_else(|e| {
+                    println!("{}", e);
+                    std::process::exit(1)
+                });
+
+            wordcloud = wordcloud.with_pinned_word(pinned);
+        }
+    }
+
+    let previous_layout = matches.value_of("previous-layout").map(|previous_layout_path| {
+        fs::read_to_string(previous_layout_path)
+            .expect("Unable to read previous layout file")
+            .parse::<Layout>()
+            .unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1)
+            })
+    });
+
+    wordcloud = wordcloud.with_previous_layout(previous_layout.clone());
+
+    // Checked before the layout, which can take a while
+    for animation_arg in ["transition", "animate"] {
+        if let Some(animation_path) = matches.value_of(animation_arg) {
+            if AnimationFormat::from_path(animation_path).is_none() {
+                eprintln!("Unsupported animation format '{}', expected a .gif or .png file", animation_path);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    #[cfg(feature = "visualize")]
+    let mut visualizer = None;
+    #[cfg(feature = "visualize")]
+    if let Some(visualize_path) = matches.value_of("visualize") {
+        let file = fs::File::create(visualize_path)
+            .expect("Unable to create visualization file");
+
+        let observer = std::sync::Arc::new(std::sync::Mutex::new(
+            wcloud::JsonObserver::new(std::io::BufWriter::new(file))
+                .with_font(matches.is_present("visualize-font"))
+        ));
+        wordcloud = wordcloud.with_observer(observer.clone());
+        visualizer = Some(observer);
+    }
+
+    if let Some(fill) = matches.value_of("fill") {
+        wordcloud = wordcloud.with_fill(
+            Some(fill.parse()
+                .expect("The fill target must be a number between 0 and 1"))
+        );
+    }
+
+    if let Some(required_words) = matches.value_of("required-words") {
+        wordcloud = wordcloud.with_required_words(
+            Some(required_words.parse()
+                .expect("The number of required words must be a valid number"))
+        );
+    }
+
+    if matches.is_present("skip-unplaceable") {
+        wordcloud = wordcloud.with_skip_unplaceable_words(true);
+    }
+
     if let Some(font_path) = matches.value_of("font") {
         let font_file = fs::read(font_path)
             .expect("Unable to read font file");
@@ -230,20 +602,63 @@ fn main() {
         .parse()
         .expect("Scale must be a number between 0 and 100");
 
-    let text = if let Some(text_file_path) = matches.value_of("text") {
-        fs::read_to_string(text_file_path)
-            .unwrap_or_else(|_| panic!("Unable to read text file \'{}\'", text_file_path))
+    let (wordcloud_image, layout, report) = wordcloud.generate_from_text_with_report(&text, wordcloud_size, scale, random_color_rgba);
+
+    #[cfg(feature = "visualize")]
+    if let Some(visualizer) = visualizer {
+        let mut visualizer = visualizer.lock().expect("Visualization observer was poisoned");
+        visualizer.flush();
+
+        if let Some(e) = visualizer.error() {
+            eprintln!("Failed to write the visualization: {}", e);
+            std::process::exit(1);
+        }
     }
-    else {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)
-            .expect("Unable to read stdin");
 
-        buffer
-    };
+    if matches.is_present("skip-unplaceable") && !report.dropped_words.is_empty() {
+        let dropped: Vec<&str> = report.dropped_words.iter().map(|word| word.text.as_str()).collect();
+        eprintln!("Dropped {} words that didn't fit: {}", dropped.len(), dropped.join(", "));
+    }
 
+    for word in report.dropped_words.iter().filter(|word| word.reason == DropReason::OffCanvas) {
+        eprintln!("The pinned word '{}' doesn't fit on the canvas where it was pinned", word.text);
+    }
 
-    let wordcloud_image = wordcloud.generate_from_text(&text, wordcloud_size, scale);
+    if let Some(layout_path) = matches.value_of("save-layout") {
+        fs::write(layout_path, layout.to_string())
+            .expect("Failed to save layout");
+    }
+
+    if let (Some(transition_path), Some(previous_layout)) = (matches.value_of("transition"), &previous_layout) {
+        let frame_count = matches.value_of("transition-frames")
+            .map_or(30, |frames| {
+                frames.parse().ok()
+                    .filter(|frames| *frames > 0)
+                    .expect("The number of transition frames must be a positive number")
+            });
+
+        let frames = wordcloud.render_transition(previous_layout, &layout, frame_count, scale, random_color_rgba);
+        save_animation(transition_path, &frames, Duration::from_millis(40))
+            .expect("Failed to save transition animation");
+    }
+
+    if let Some(animation_path) = matches.value_of("animate") {
+        let words_per_frame = matches.value_of("words-per-frame")
+            .map_or(1, |words| {
+                words.parse().ok()
+                    .filter(|words| *words > 0)
+                    .expect("The number of words per frame must be a positive number")
+            });
+
+        let frames = wordcloud.render_placement(&layout, words_per_frame, scale, random_color_rgba);
+        save_animation(animation_path, &frames, Duration::from_millis(40))
+            .expect("Failed to save placement animation");
+    }
+
+    if let Some(report_path) = matches.value_of("report") {
+        fs::write(report_path, report.to_json())
+            .expect("Failed to save placement report");
+    }
 
     if let Some(file_path) = matches.value_of("output") {
         wordcloud_image.save(file_path)
@@ -0,0 +1,280 @@
+use std::sync::{Arc, Mutex};
+use ab_glyph::FontVec;
+use image::{GrayImage, Rgba};
+use crate::{DroppedWord, PlacedWord};
+use crate::sat::Rect;
+
+#[cfg(feature = "visualize")]
+use std::io::{self, Write};
+#[cfg(feature = "visualize")]
+use crate::visualize::{self, Message, Init, CheckRect, PlaceWord};
+
+/// Gets told about each step of laying out a word cloud, see [`crate::WordCloud::with_observer`]
+///
+/// Every method does nothing by default, so only the ones of interest need to be implemented.
+pub trait PlacementObserver: Send {
+    /// A layout is starting on a canvas of this size
+    fn init(&mut self, _width: u32, _height: u32, _mask: Option<&GrayImage>, _font: &FontVec, _background_color: Rgba<u8>) {}
+
+    /// Space is about to be searched for the word at this size. `rect` includes the word margin
+    fn word_attempt(&mut self, _text: &str, _font_size: f32, _rect: &Rect, _rotated: bool) {}
+
+    /// The word's rect was checked with its top left corner at (`x`, `y`)
+    ///
+    /// This can be called millions of times per word. The bitmap occupancy backend checks every
+    /// position at once, so it never calls this.
+    fn rect_check(&mut self, _x: u32, _y: u32, _empty: bool) {}
+
+    fn word_placed(&mut self, _word: &PlacedWord) {}
+
+    fn word_dropped(&mut self, _word: &DroppedWord) {}
+}
+
+/// Lets the observer be looked at once the word cloud is done, by keeping a clone of the `Arc`
+impl<T: PlacementObserver> PlacementObserver for Arc<Mutex<T>> {
+    fn init(&mut self, width: u32, height: u32, mask: Option<&GrayImage>, font: &FontVec, background_color: Rgba<u8>) {
+        self.lock().expect("Placement observer was poisoned").init(width, height, mask, font, background_color);
+    }
+
+    fn word_attempt(&mut self, text: &str, font_size: f32, rect: &Rect, rotated: bool) {
+        self.lock().expect("Placement observer was poisoned").word_attempt(text, font_size, rect, rotated);
+    }
+
+    fn rect_check(&mut self, x: u32, y: u32, empty: bool) {
+        self.lock().expect("Placement observer was poisoned").rect_check(x, y, empty);
+    }
+
+    fn word_placed(&mut self, word: &PlacedWord) {
+        self.lock().expect("Placement observer was poisoned").word_placed(word);
+    }
+
+    fn word_dropped(&mut self, word: &DroppedWord) {
+        self.lock().expect("Placement observer was poisoned").word_dropped(word);
+    }
+}
+
+/// Writes every placement step as a line of JSON, for the placement visualizer
+///
+/// Nothing more is written after the first error, which can be checked with
+/// [`JsonObserver::error`]. Wrap it in an `Arc<Mutex<_>>` to get at it after the word cloud is done.
+#[cfg(feature = "visualize")]
+pub struct JsonObserver<W> {
+    writer: W,
+    include_font: bool,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "visualize")]
+impl<W: Write + Send> JsonObserver<W> {
+    pub fn new(writer: W) -> Self {
+        JsonObserver { writer, include_font: false, error: None }
+    }
+
+    /// Includes the bytes of the font in the first message, for visualizers that draw the words
+    pub fn with_font(mut self, value: bool) -> Self {
+        self.include_font = value;
+        self
+    }
+
+    /// The error that stopped messages from being written, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Flushes the writer. A failure is kept as the error, like a failed write
+    pub fn flush(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn write(&mut self, message: &Message) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let serialized = serde_json::to_string(message).unwrap();
+        if let Err(e) = writeln!(self.writer, "{}", serialized) {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[cfg(feature = "visualize")]
+impl<W: Write + Send> PlacementObserver for JsonObserver<W> {
+    fn init(&mut self, width: u32, height: u32, mask: Option<&GrayImage>, font: &FontVec, background_color: Rgba<u8>) {
+        self.write(&Message::InitMessage(Init {
+            width,
+            height,
+            mask: mask.map(|mask| mask.to_vec()),
+            font: self.include_font.then(|| font.as_slice().to_vec()),
+            background_color: background_color.0,
+        }));
+    }
+
+    fn word_attempt(&mut self, text: &str, font_size: f32, rect: &Rect, rotated: bool) {
+        self.write(&Message::ChangeWordMessage(visualize::Word {
+            text: text.to_string(),
+            font_size: font_size as u32,
+            rect_width: rect.width,
+            rect_height: rect.height,
+            rotation: if rotated { 270 } else { 0 },
+        }));
+    }
+
+    fn rect_check(&mut self, x: u32, y: u32, empty: bool) {
+        self.write(&Message::CheckRectMessage(CheckRect { x, y, empty }));
+    }
+
+    fn word_placed(&mut self, word: &PlacedWord) {
+        self.write(&Message::PlacedWordMessage(PlaceWord {
+            text: word.text.clone(),
+            font_size: word.font_size as u32,
+            rotation: if word.rotated { 270 } else { 0 },
+            x: word.position.x as u32,
+            y: word.position.y as u32,
+        }));
+    }
+
+    fn word_dropped(&mut self, word: &DroppedWord) {
+        self.write(&Message::DroppedWordMessage(visualize::DroppedWord {
+            text: word.text.clone(),
+            reason: word.reason.as_str(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{wordcloud, size};
+
+    #[derive(Default)]
+    struct Counts {
+        inits: usize,
+        attempts: usize,
+        checks: usize,
+        placed: Vec<String>,
+        dropped: Vec<String>,
+    }
+
+    struct CountingObserver(Arc<Mutex<Counts>>);
+
+    impl PlacementObserver for CountingObserver {
+        fn init(&mut self, _width: u32, _height: u32, _mask: Option<&GrayImage>, _font: &FontVec, _background_color: Rgba<u8>) {
+            self.0.lock().unwrap().inits += 1;
+        }
+
+        fn word_attempt(&mut self, _text: &str, _font_size: f32, _rect: &Rect, _rotated: bool) {
+            self.0.lock().unwrap().attempts += 1;
+        }
+
+        fn rect_check(&mut self, _x: u32, _y: u32, _empty: bool) {
+            self.0.lock().unwrap().checks += 1;
+        }
+
+        fn word_placed(&mut self, word: &PlacedWord) {
+            self.0.lock().unwrap().placed.push(word.text.clone());
+        }
+
+        fn word_dropped(&mut self, word: &DroppedWord) {
+            self.0.lock().unwrap().dropped.push(word.text.clone());
+        }
+    }
+
+    #[test]
+    fn observer_sees_every_step() {
+        let counts = Arc::new(Mutex::new(Counts::default()));
+        let layout = wordcloud(1)
+            .with_min_font_size(40.0)
+            .with_skip_unplaceable_words(true)
+            .with_observer(CountingObserver(counts.clone()))
+            .generate_layout("rust rust rust cargo cargo crate borrowchecker", size(200, 100));
+
+        let counts = counts.lock().unwrap();
+        assert_eq!(counts.inits, 1);
+        assert!(counts.attempts >= layout.words.len());
+        assert!(counts.checks > 0);
+        assert_eq!(counts.placed, layout.words.iter().map(|word| word.text.clone()).collect::<Vec<_>>());
+        assert_eq!(counts.dropped, layout.dropped_words.iter().map(|word| word.text.clone()).collect::<Vec<_>>());
+        assert!(!counts.dropped.is_empty());
+    }
+
+    #[test]
+    fn observer_only_sees_the_kept_fit_attempt() {
+        let counts = Arc::new(Mutex::new(Counts::default()));
+        let layout = wordcloud(3)
+            .with_min_font_size(40.0)
+            .with_relative_font_scaling(0.0)
+            .with_word_rotate_chance(0.0)
+            .with_required_words(Some(5))
+            .with_observer(CountingObserver(counts.clone()))
+            .generate_layout("alpha alpha alpha alpha alpha bravo bravo bravo bravo charlie charlie charlie delta delta echo", size(200, 200));
+
+        let counts = counts.lock().unwrap();
+        assert_eq!(counts.placed, layout.words.iter().map(|word| word.text.clone()).collect::<Vec<_>>());
+        assert!(counts.dropped.is_empty());
+    }
+
+    #[test]
+    fn observer_hears_every_generation_of_a_shared_word_cloud() {
+        let counts = Arc::new(Mutex::new(Counts::default()));
+        let wordcloud = wordcloud(3)
+            .with_min_font_size(40.0)
+            .with_relative_font_scaling(0.0)
+            .with_required_words(Some(5))
+            .with_observer(CountingObserver(counts.clone()));
+
+        let text = "alpha alpha alpha alpha alpha bravo bravo bravo bravo charlie charlie charlie delta delta echo";
+        let placed: usize = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| wordcloud.generate_layout(text, size(200, 200)).words.len()))
+                .collect();
+
+            handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+        });
+
+        let counts = counts.lock().unwrap();
+        assert_eq!(counts.inits, 4);
+        assert_eq!(counts.placed.len(), placed);
+    }
+
+    #[cfg(feature = "visualize")]
+    #[test]
+    fn json_observer_can_be_checked_after_generating() {
+        let observer = Arc::new(Mutex::new(JsonObserver::new(Vec::new())));
+        let layout = wordcloud(1)
+            .with_observer(observer.clone())
+            .generate_layout("rust rust rust cargo cargo crate", size(200, 100));
+
+        let mut observer = observer.lock().unwrap();
+        observer.flush();
+        assert!(observer.error().is_none());
+
+        let written = String::from_utf8(observer.writer.clone()).unwrap();
+        assert_eq!(written.lines().filter(|line| line.contains("PlacedWordMessage")).count(), layout.words.len());
+    }
+
+    #[cfg(feature = "visualize")]
+    #[test]
+    fn json_observer_keeps_the_first_error() {
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut observer = JsonObserver::new(Full);
+        observer.rect_check(1, 2, true);
+        observer.rect_check(3, 4, false);
+        assert_eq!(observer.error().map(io::Error::kind), Some(io::ErrorKind::StorageFull));
+    }
+}
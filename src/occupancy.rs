@@ -0,0 +1,365 @@
+use std::str::FromStr;
+use image::GrayImage;
+use nanorand::Rng;
+use crate::bias::Reservoir;
+use crate::sat::{self, CheckCallback, Point, Rect, Span};
+
+/// How the placement keeps track of which pixels of the canvas are already taken
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum OccupancyBackend {
+    /// A byte per pixel plus a summed-area table of them, which uses 5 bytes per pixel
+    #[default]
+    SummedAreaTable,
+    /// A summed-area table that rules out or accepts whole blocks of positions before checking
+    /// single ones, which is much faster on large canvases
+    Pyramid,
+    /// A single bit per pixel, for canvases too large to fit a summed-area table in memory
+    Bitmap,
+}
+
+impl FromStr for OccupancyBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sat" | "summed-area-table" => Ok(OccupancyBackend::SummedAreaTable),
+            "pyramid" => Ok(OccupancyBackend::Pyramid),
+            "bitmap" => Ok(OccupancyBackend::Bitmap),
+            _ => Err(format!("Unknown occupancy backend '{}'", s)),
+        }
+    }
+}
+
+/// The pixels of the canvas that words or the mask already cover
+#[derive(Clone)]
+pub(crate) enum Occupancy {
+    SummedAreaTable { image: GrayImage, table: Vec<u32>, pyramid: bool },
+    Bitmap(Bitmap),
+}
+
+impl Occupancy {
+    /// Any non-zero pixel of the mask is taken
+    pub fn new(backend: OccupancyBackend, width: u32, height: u32, mask: Option<GrayImage>) -> Self {
+        match backend {
+            OccupancyBackend::SummedAreaTable | OccupancyBackend::Pyramid => {
+                let image = mask.unwrap_or_else(|| GrayImage::new(width, height));
+                let pyramid = backend == OccupancyBackend::Pyramid;
+                let mut occupancy = Occupancy::SummedAreaTable { table: vec![0; image.len()], image, pyramid };
+                occupancy.update();
+                occupancy
+            },
+            OccupancyBackend::Bitmap => {
+                let mut bitmap = Bitmap::new(width, height);
+                if let Some(mask) = mask {
+                    for (x, y, p) in mask.enumerate_pixels() {
+                        if p.0[0] != 0 {
+                            bitmap.set(x, y);
+                        }
+                    }
+                }
+                Occupancy::Bitmap(bitmap)
+            },
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            Occupancy::SummedAreaTable { image, .. } => image.width(),
+            Occupancy::Bitmap(bitmap) => bitmap.width,
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Occupancy::SummedAreaTable { image, .. } => image.height(),
+            Occupancy::Bitmap(bitmap) => bitmap.height,
+        }
+    }
+
+    pub fn mark(&mut self, x: u32, y: u32) {
+        match self {
+            Occupancy::SummedAreaTable { image, .. } => image.put_pixel(x, y, image::Luma([1])),
+            Occupancy::Bitmap(bitmap) => bitmap.set(x, y),
+        }
+    }
+
+    /// Brings the search structures up to date after pixels have been marked
+    pub fn update(&mut self) {
+        if let Occupancy::SummedAreaTable { image, table, .. } = self {
+            // TODO: Do a partial sat like the Python implementation
+            for (el, p) in table.iter_mut().zip(image.as_raw()) {
+                *el = (*p != 0) as u32;
+            }
+            sat::to_summed_area_table(table, image.width() as usize, 0);
+        }
+    }
+
+    pub fn free_pixels(&self) -> usize {
+        match self {
+            Occupancy::SummedAreaTable { image, .. } => image.as_raw().iter().filter(|p| **p == 0).count(),
+            Occupancy::Bitmap(bitmap) => bitmap.free_pixels(),
+        }
+    }
+
+    /// Whether the rect can go at (`x`, `y`), using the same positions as
+    /// [`Occupancy::find_space_for_rect`]
+    pub fn rect_is_free(&self, rect: &Rect, x: u32, y: u32) -> bool {
+        match self {
+            Occupancy::SummedAreaTable { image, table, .. } => {
+                x.saturating_add(rect.width) < image.width() && y.saturating_add(rect.height) < image.height()
+                    && sat::region_is_empty(table, image.width() as usize, x as usize, y as usize, rect.width as usize, rect.height as usize)
+            },
+            Occupancy::Bitmap(bitmap) => {
+                x.saturating_add(rect.width) <= bitmap.width && y.saturating_add(rect.height) <= bitmap.height
+                    && (y..y + rect.height).all(|y| (x..x + rect.width).all(|x| !bitmap.is_set(x, y)))
+            },
+        }
+    }
+
+    /// Picks a random position where the rect doesn't overlap anything
+    pub fn find_space_for_rect(&self, rect: &Rect, mask_spans: Option<&[Vec<Span>]>, reservoir: &mut Reservoir, on_check: CheckCallback) -> Option<Point> {
+        match self {
+            Occupancy::SummedAreaTable { image, table, pyramid: true } => {
+                sat::search_for_rect_pyramid(table, image.width(), image.height(), rect, reservoir, on_check);
+                reservoir.point.clone()
+            },
+            Occupancy::SummedAreaTable { image, table, pyramid: false } => {
+                sat::search_for_rect(table, image.width(), image.height(), mask_spans, rect, reservoir, on_check);
+                reservoir.point.clone()
+            },
+            Occupancy::Bitmap(bitmap) => bitmap.find_space_for_rect(rect, reservoir),
+        }
+    }
+}
+
+/// A bit per pixel, set when the pixel is taken
+///
+/// Rows are padded to whole `u64`s, and the padding is always taken.
+#[derive(Clone, Debug)]
+pub(crate) struct Bitmap {
+    width: u32,
+    height: u32,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        let words_per_row = (width as usize).div_ceil(64).max(1);
+        let mut bits = vec![0; words_per_row * height as usize];
+
+        let padding = words_per_row * 64 - width as usize;
+        if padding > 0 {
+            let padding_mask = !0u64 << (64 - padding);
+            for row in bits.chunks_exact_mut(words_per_row) {
+                row[words_per_row - 1] |= padding_mask;
+            }
+        }
+
+        Bitmap { width, height, words_per_row, bits }
+    }
+
+    pub fn set(&mut self, x: u32, y: u32) {
+        assert!(x < self.width && y < self.height, "Pixel ({}, {}) is outside the canvas", x, y);
+        let index = y as usize * self.words_per_row + x as usize / 64;
+        self.bits[index] |= 1 << (x % 64);
+    }
+
+    fn is_set(&self, x: u32, y: u32) -> bool {
+        let index = y as usize * self.words_per_row + x as usize / 64;
+        self.bits[index] & (1 << (x % 64)) != 0
+    }
+
+    pub fn free_pixels(&self) -> usize {
+        self.bits.iter().map(|word| word.count_zeros() as usize).sum()
+    }
+
+    /// Returns a bit per pixel that is set when a rect with its top left corner there would only
+    /// cover free pixels
+    ///
+    /// Both directions are done by doubling: a run of `2n` free pixels is a run of `n` free pixels
+    /// followed by another, so only `log(width) + log(height)` passes over the bitmap are needed.
+    fn fitting_positions(&self, rect: &Rect) -> Vec<u64> {
+        let words_per_row = self.words_per_row;
+        let free: Vec<u64> = self.bits.iter().map(|word| !word).collect();
+
+        let mut horizontal = vec![0; free.len()];
+        for (row, out) in free.chunks_exact(words_per_row).zip(horizontal.chunks_exact_mut(words_per_row)) {
+            out.copy_from_slice(&runs(row, rect.width as usize, shift_bits));
+        }
+
+        let shift_rows = |values: &[u64], by: usize| {
+            let offset = (by * words_per_row).min(values.len());
+            let mut shifted = vec![0; values.len()];
+            shifted[..values.len() - offset].copy_from_slice(&values[offset..]);
+            shifted
+        };
+
+        runs(&horizontal, rect.height as usize, shift_rows)
+    }
+
+    pub fn find_space_for_rect(&self, rect: &Rect, reservoir: &mut Reservoir) -> Option<Point> {
+        if rect.width == 0 || rect.height == 0 || rect.width > self.width || rect.height > self.height {
+            return None;
+        }
+
+        let positions = self.fitting_positions(rect);
+        let last_row = (self.height - rect.height) as usize;
+
+        if !reservoir.is_uniform() {
+            for (i, word) in positions[..(last_row + 1) * self.words_per_row].iter().enumerate() {
+                let mut word = *word;
+                while word != 0 {
+                    let x = (i % self.words_per_row) * 64 + word.trailing_zeros() as usize;
+                    reservoir.add(x as u32, (i / self.words_per_row) as u32);
+                    word &= word - 1;
+                }
+            }
+            return reservoir.point.clone();
+        }
+
+        let available_points: u64 = positions[..(last_row + 1) * self.words_per_row].iter()
+            .map(|word| word.count_ones() as u64)
+            .sum();
+
+        if available_points == 0 {
+            return None;
+        }
+
+        // Every set bit is an equally likely spot
+        let mut remaining = reservoir.rng.generate_range(0..available_points);
+        for (i, word) in positions.iter().enumerate() {
+            let count = word.count_ones() as u64;
+            if remaining >= count {
+                remaining -= count;
+                continue;
+            }
+
+            let mut word = *word;
+            for _ in 0..remaining {
+                word &= word - 1;
+            }
+
+            let x = (i % self.words_per_row) * 64 + word.trailing_zeros() as usize;
+            let y = i / self.words_per_row;
+            return Some(Point { x: x as u32, y: y as u32 });
+        }
+
+        None
+    }
+}
+
+/// Combines `n` consecutive elements with AND, so that element `i` of the result says whether
+/// elements `i..i + n` were all set
+///
+/// `shift` moves the elements `by` places towards the start, filling the end with unset bits.
+fn runs(values: &[u64], n: usize, shift: impl Fn(&[u64], usize) -> Vec<u64>) -> Vec<u64> {
+    let and = |a: &mut Vec<u64>, b: Vec<u64>| a.iter_mut().zip(b).for_each(|(a, b)| *a &= b);
+
+    let mut result: Option<Vec<u64>> = None;
+    let mut result_len = 0;
+
+    let mut current = values.to_vec();
+    let mut current_len = 1;
+    let mut remaining = n;
+
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            match result.as_mut() {
+                None => result = Some(current.clone()),
+                Some(result) => and(result, shift(&current, result_len)),
+            }
+            result_len += current_len;
+        }
+
+        remaining >>= 1;
+        if remaining > 0 {
+            let shifted = shift(&current, current_len);
+            and(&mut current, shifted);
+            current_len *= 2;
+        }
+    }
+
+    result.unwrap_or(current)
+}
+
+/// Moves every bit `by` places towards the start of the row, filling the end with unset bits
+fn shift_bits(row: &[u64], by: usize) -> Vec<u64> {
+    let words = by / 64;
+    let bits = by % 64;
+
+    (0..row.len())
+        .map(|i| {
+            let low = row.get(i + words).copied().unwrap_or(0);
+            let high = row.get(i + words + 1).copied().unwrap_or(0);
+
+            if bits == 0 {
+                low
+            }
+            else {
+                (low >> bits) | (high << (64 - bits))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nanorand::WyRand;
+
+    fn brute_force_positions(bitmap: &Bitmap, rect: &Rect) -> Vec<(u32, u32)> {
+        let mut positions = Vec::new();
+        for y in 0..=bitmap.height - rect.height {
+            for x in 0..=bitmap.width - rect.width {
+                let free = (y..y + rect.height).all(|y| (x..x + rect.width).all(|x| !bitmap.is_set(x, y)));
+                if free {
+                    positions.push((x, y));
+                }
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn bitmap_finds_the_same_spaces_as_brute_force() {
+        let mut bitmap = Bitmap::new(150, 20);
+        for x in 40..90 {
+            bitmap.set(x, 5);
+        }
+        bitmap.set(3, 12);
+        bitmap.set(130, 0);
+
+        for rect in [Rect { width: 1, height: 1 }, Rect { width: 70, height: 3 }, Rect { width: 13, height: 14 }] {
+            let expected = brute_force_positions(&bitmap, &rect);
+            let positions = bitmap.fitting_positions(&rect);
+
+            let found: Vec<(u32, u32)> = (0..=bitmap.height - rect.height)
+                .flat_map(|y| (0..=bitmap.width - rect.width).map(move |x| (x, y)))
+                .filter(|(x, y)| positions[*y as usize * bitmap.words_per_row + *x as usize / 64] & (1 << (x % 64)) != 0)
+                .collect();
+
+            assert_eq!(found, expected);
+        }
+
+        let mut rng = WyRand::new_seed(1);
+        let rect = Rect { width: 40, height: 20 };
+        let pos = bitmap.find_space_for_rect(&rect, &mut Reservoir::new(None, &rect, 150, 20, &mut rng)).unwrap();
+        assert_eq!((pos.x, pos.y), (90, 0));
+        let rect = Rect { width: 151, height: 1 };
+        assert!(bitmap.find_space_for_rect(&rect, &mut Reservoir::new(None, &rect, 150, 20, &mut rng)).is_none());
+        assert_eq!(bitmap.free_pixels(), 150 * 20 - 52);
+    }
+
+    #[test]
+    fn mask_values_are_normalized() {
+        let mut mask = GrayImage::new(4, 4);
+        mask.put_pixel(1, 1, image::Luma([255]));
+        let occupancy = Occupancy::new(OccupancyBackend::SummedAreaTable, 4, 4, Some(mask));
+
+        if let Occupancy::SummedAreaTable { table, .. } = &occupancy {
+            assert_eq!(table[15], 1);
+        }
+        assert_eq!(occupancy.free_pixels(), 15);
+    }
+}
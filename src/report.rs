@@ -0,0 +1,144 @@
+use std::fmt::Write;
+use std::time::Duration;
+use crate::{DroppedWord, Layout};
+
+/// A summary of how a word cloud was laid out and drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacementReport {
+    pub placed_words: usize,
+    /// How many of the placed words are copies that fill mode added
+    pub fill_words: usize,
+    /// The words that didn't make it onto the canvas and why, heaviest first
+    pub dropped_words: Vec<DroppedWord>,
+    /// The fraction of the usable canvas area that words were drawn on
+    pub coverage: f32,
+    /// The smallest and largest font sizes of the placed words
+    pub font_size_range: Option<(f32, f32)>,
+    pub layout_time: Duration,
+    pub render_time: Duration,
+}
+
+impl PlacementReport {
+    pub(crate) fn new(layout: &Layout, layout_time: Duration, render_time: Duration) -> Self {
+        let font_size_range = layout.words.iter()
+            .map(|word| word.font_size)
+            .fold(None, |range: Option<(f32, f32)>, size| match range {
+                Some((min, max)) => Some((min.min(size), max.max(size))),
+                None => Some((size, size)),
+            });
+
+        PlacementReport {
+            placed_words: layout.words.len(),
+            fill_words: layout.fill_words,
+            dropped_words: layout.dropped_words.clone(),
+            coverage: layout.coverage,
+            font_size_range,
+            layout_time,
+            render_time,
+        }
+    }
+
+    /// The number of words that were considered for placement, not counting fill copies
+    pub fn total_words(&self) -> usize {
+        self.placed_words - self.fill_words + self.dropped_words.len()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+
+        let _ = writeln!(json, "  \"total_words\": {},", self.total_words());
+        let _ = writeln!(json, "  \"placed_words\": {},", self.placed_words);
+        let _ = writeln!(json, "  \"fill_words\": {},", self.fill_words);
+
+        json.push_str("  \"dropped_words\": [");
+        for (i, word) in self.dropped_words.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json, "\n    {{ \"text\": {}, \"frequency\": {}, \"reason\": \"{}\" }}",
+                json_string(&word.text), word.frequency, word.reason.as_str()
+            );
+        }
+        if !self.dropped_words.is_empty() {
+            json.push_str("\n  ");
+        }
+        json.push_str("],\n");
+
+        let _ = writeln!(json, "  \"coverage\": {},", self.coverage);
+        match self.font_size_range {
+            Some((min, max)) => {
+                let _ = writeln!(json, "  \"font_size_range\": [{}, {}],", min, max);
+            },
+            None => json.push_str("  \"font_size_range\": null,\n"),
+        }
+        let _ = writeln!(json, "  \"layout_ms\": {},", self.layout_time.as_secs_f64() * 1000.0);
+        let _ = writeln!(json, "  \"render_ms\": {}", self.render_time.as_secs_f64() * 1000.0);
+
+        json.push('}');
+        json
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            },
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DropReason, PlacedWord};
+    use ab_glyph::point;
+
+    #[test]
+    fn report_to_json() {
+        let layout = Layout {
+            width: 100,
+            height: 50,
+            words: vec![
+                PlacedWord { text: "big".to_string(), font_size: 40.0, rotated: false, position: point(0.0, 0.0), frequency: 1.0 },
+                PlacedWord { text: "small".to_string(), font_size: 10.0, rotated: true, position: point(60.0, 0.0), frequency: 0.5 },
+                PlacedWord { text: "big".to_string(), font_size: 10.0, rotated: false, position: point(0.0, 40.0), frequency: 1.0 },
+            ],
+            dropped_words: vec![
+                DroppedWord { text: "say \"hi\"".to_string(), frequency: 0.25, reason: DropReason::NoSpace },
+            ],
+            coverage: 0.5,
+            fill_words: 1,
+        };
+
+        let report = PlacementReport::new(&layout, Duration::from_millis(2), Duration::from_millis(1));
+        assert_eq!(report.total_words(), 3);
+        assert_eq!(report.font_size_range, Some((10.0, 40.0)));
+        assert_eq!(report.to_json(), r#"{
+  "total_words": 3,
+  "placed_words": 3,
+  "fill_words": 1,
+  "dropped_words": [
+    { "text": "say \"hi\"", "frequency": 0.25, "reason": "no-space" }
+  ],
+  "coverage": 0.5,
+  "font_size_range": [10, 40],
+  "layout_ms": 2,
+  "render_ms": 1
+}"#);
+    }
+}
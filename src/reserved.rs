@@ -0,0 +1,132 @@
+use std::str::FromStr;
+use crate::sat::Region;
+
+/// An area of the canvas that words are kept out of, such as where a logo or title will go
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReservedRegion {
+    Rect(Region),
+    /// The corners of the polygon in order. Self-intersecting polygons are filled with the
+    /// even-odd rule
+    Polygon(Vec<(f32, f32)>),
+}
+
+impl ReservedRegion {
+    /// Calls `mark` for every pixel of the canvas inside the region
+    pub fn mark(&self, canvas_width: u32, canvas_height: u32, mut mark: impl FnMut(u32, u32)) {
+        match self {
+            ReservedRegion::Rect(region) => {
+                let end_x = region.x.saturating_add(region.width).min(canvas_width);
+                let end_y = region.y.saturating_add(region.height).min(canvas_height);
+
+                for y in region.y..end_y {
+                    for x in region.x..end_x {
+                        mark(x, y);
+                    }
+                }
+            },
+            ReservedRegion::Polygon(points) => {
+                if points.len() < 3 {
+                    return;
+                }
+
+                let mut crossings = Vec::new();
+
+                for y in 0..canvas_height {
+                    // Pixels are inside when their center is
+                    let scan_y = y as f32 + 0.5;
+
+                    crossings.clear();
+                    for (i, &(x1, y1)) in points.iter().enumerate() {
+                        let (x2, y2) = points[(i + 1) % points.len()];
+                        if (y1 <= scan_y) != (y2 <= scan_y) {
+                            crossings.push(x1 + (scan_y - y1) / (y2 - y1) * (x2 - x1));
+                        }
+                    }
+                    crossings.sort_by(|a, b| a.total_cmp(b));
+
+                    for pair in crossings.chunks_exact(2) {
+                        let start_x = (pair[0] - 0.5).ceil().max(0.0) as u32;
+                        let end_x = ((pair[1] - 0.5).ceil().max(0.0) as u32).min(canvas_width);
+
+                        for x in start_x..end_x {
+                            mark(x, y);
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Parses `rect:X,Y,WIDTH,HEIGHT` or `polygon:X1,Y1;X2,Y2;X3,Y3...`
+impl FromStr for ReservedRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid reserved region '{}', expected rect:X,Y,WIDTH,HEIGHT or polygon:X1,Y1;X2,Y2;...", s);
+
+        match s.split_once(':') {
+            Some(("rect", values)) => {
+                let values = values.split(',')
+                    .map(|value| value.trim().parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid())?;
+
+                match values[..] {
+                    [x, y, width, height] => Ok(ReservedRegion::Rect(Region { x, y, width, height })),
+                    _ => Err(invalid()),
+                }
+            },
+            Some(("polygon", points)) => {
+                let points = points.split(';')
+                    .map(|point| {
+                        let (x, y) = point.split_once(',')?;
+                        Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?;
+
+                if points.len() < 3 {
+                    return Err(format!("The polygon '{}' needs at least 3 points", s));
+                }
+
+                Ok(ReservedRegion::Polygon(points))
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marked(region: &ReservedRegion, width: u32, height: u32) -> Vec<(u32, u32)> {
+        let mut pixels = Vec::new();
+        region.mark(width, height, |x, y| pixels.push((x, y)));
+        pixels
+    }
+
+    #[test]
+    fn polygon_matches_rect() {
+        let rect = "rect:2,3,5,4".parse::<ReservedRegion>().unwrap();
+        let polygon = "polygon:2,3;7,3;7,7;2,7".parse::<ReservedRegion>().unwrap();
+        assert_eq!(marked(&rect, 20, 20), marked(&polygon, 20, 20));
+        assert_eq!(marked(&rect, 20, 20).len(), 20);
+
+        // Clipped to the canvas
+        assert_eq!(marked(&rect, 4, 5).len(), 4);
+    }
+
+    #[test]
+    fn triangle_is_half_filled() {
+        let triangle = ReservedRegion::Polygon(vec![(0.0, 0.0), (100.0, 0.0), (0.0, 100.0)]);
+        let pixels = marked(&triangle, 100, 100);
+        assert!(pixels.contains(&(10, 10)));
+        assert!(!pixels.contains(&(60, 60)));
+        assert!((pixels.len() as i32 - 5000).abs() < 100);
+
+        assert!("polygon:0,0;1,1".parse::<ReservedRegion>().is_err());
+        assert!("circle:1,2,3".parse::<ReservedRegion>().is_err());
+    }
+}
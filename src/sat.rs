@@ -1,13 +1,9 @@
 // Naive implementation of a summed-area table
 // https://en.wikipedia.org/wiki/Summed-area_table
-use nanorand::{Rng, WyRand};
+use nanorand::WyRand;
+use crate::bias::Reservoir;
 
-#[cfg(feature = "visualize")]
-use std::io::{BufWriter, stdout, Write};
-#[cfg(feature = "visualize")]
-use crate::visualize::{Message, CheckRect};
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Region {
     pub x: u32,
     pub y: u32,
@@ -21,6 +17,13 @@ pub struct Rect {
     pub height: u32,
 }
 
+/// A run of usable pixels in a row of a mask, from `start` up to but not including `end`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct Point {
     pub x: u32,
@@ -34,7 +37,9 @@ pub fn region_is_empty(table: &[u32], table_width: usize, x: usize, y: usize, wi
     let bl = table[(y + height) * table_width + x];
     let br = table[(y + height) * table_width + x + width];
 
-    tl as i32 + br as i32 - tr as i32 - bl as i32 == 0
+    // The sum of a region is never negative, so wrapping gives the right answer even when the
+    // corners themselves have overflowed
+    tl.wrapping_add(br).wrapping_sub(tr).wrapping_sub(bl) == 0
 }
 
 pub fn find_space_for_rect(
@@ -44,90 +49,171 @@ pub fn find_space_for_rect(
     rect: &Rect,
     rng: &mut WyRand,
 ) -> Option<Point> {
+    let mut reservoir = Reservoir::new(None, rect, table_width, table_height, rng);
+    search_for_rect(table, table_width, table_height, None, rect, &mut reservoir, None);
+    reservoir.point
+}
+
+pub fn find_space_for_rect_masked(
+    table: &[u32],
+    table_width: u32,
+    table_height: u32,
+    mask_spans: &[Vec<Span>],
+    rect: &Rect,
+    rng: &mut WyRand,
+) -> Option<Point> {
+    let mut reservoir = Reservoir::new(None, rect, table_width, table_height, rng);
+    search_for_rect(table, table_width, table_height, Some(mask_spans), rect, &mut reservoir, None);
+    reservoir.point
+}
+
+/// Called with every position that is checked and whether the rect fits there
+pub(crate) type CheckCallback<'a> = Option<&'a mut dyn FnMut(u32, u32, bool)>;
+
+/// Shows every position where the rect fits to the reservoir
+pub(crate) fn search_for_rect(
+    table: &[u32],
+    table_width: u32,
+    table_height: u32,
+    mask_spans: Option<&[Vec<Span>]>,
+    rect: &Rect,
+    reservoir: &mut Reservoir,
+    mut on_check: CheckCallback,
+) {
     let max_x = table_width - rect.width;
     let max_y = table_height - rect.height;
 
-    let mut available_points = 0;
-    let mut random_point = None;
-
-    #[cfg(feature = "visualize")]
-    let mut visualize_buf = BufWriter::new(stdout());
+    let full_row = [Span { start: 0, end: table_width }];
 
     for y in 0..max_y {
-        for x in 0..max_x {
-            let empty = region_is_empty(table, table_width as usize, x as usize, y as usize, rect.width as usize, rect.height as usize);
-
-            #[cfg(feature = "visualize")]
-            {
-                let serialized = serde_json::to_string(&Message::CheckRectMessage(CheckRect {
-                    x,
-                    y,
-                    empty,
-                })).unwrap();
-                writeln!(visualize_buf, "{}", serialized).unwrap();
-            };
+        // A rect at (x, y) covers the pixels from x + 1 to x + width of the rows below y, so it
+        // can only fit where the next row of the mask has a span that long
+        let spans = mask_spans.map_or(&full_row[..], |spans| &spans[y as usize + 1]);
 
-            if empty {
-                // https://en.wikipedia.org/wiki/Reservoir_sampling
-                let random_num = rng.generate_range(0..=available_points);
-                if random_num == available_points {
-                    random_point = Some(Point { x, y });
+        for span in spans {
+            let start = span.start.saturating_sub(1);
+            let end = (span.end.saturating_sub(rect.width)).min(max_x);
+
+            for x in start..end {
+                let empty = region_is_empty(table, table_width as usize, x as usize, y as usize, rect.width as usize, rect.height as usize);
+
+                if let Some(on_check) = on_check.as_mut() {
+                    on_check(x, y, empty);
+                }
+
+                if empty {
+                    reservoir.add(x, y);
                 }
-                available_points += 1;
             }
         }
     }
-
-    random_point
 }
 
-pub fn find_space_for_rect_masked(
+/// The sizes of the blocks of positions that [`find_space_for_rect_pyramid`] checks at once,
+/// from coarsest to finest
+const PYRAMID_LEVELS: [u32; 3] = [64, 8, 1];
+
+/// Same as [`find_space_for_rect`], but checks whole blocks of positions before single ones
+///
+/// The summed-area table can tell in constant time whether anything in a block is taken, so it
+/// serves as every level of the pyramid. A block is rejected when the area that every rect in it
+/// would cover is taken, and accepted when the area that any rect in it would cover is empty.
+/// Accepted blocks count as all of their positions, so every free position is still equally
+/// likely to be picked.
+pub fn find_space_for_rect_pyramid(
     table: &[u32],
     table_width: u32,
     table_height: u32,
-    skip_list: &[(usize, usize)],
     rect: &Rect,
     rng: &mut WyRand,
 ) -> Option<Point> {
+    let mut reservoir = Reservoir::new(None, rect, table_width, table_height, rng);
+    search_for_rect_pyramid(table, table_width, table_height, rect, &mut reservoir, None);
+    reservoir.point
+}
+
+pub(crate) fn search_for_rect_pyramid(
+    table: &[u32],
+    table_width: u32,
+    table_height: u32,
+    rect: &Rect,
+    reservoir: &mut Reservoir,
+    on_check: CheckCallback,
+) {
     let max_x = table_width - rect.width;
     let max_y = table_height - rect.height;
 
-    let mut available_points = 0;
-    let mut random_point = None;
+    let mut search = PyramidSearch {
+        table,
+        table_width: table_width as usize,
+        max_x,
+        max_y,
+        rect,
+        reservoir,
+        on_check,
+    };
+
+    let block_size = PYRAMID_LEVELS[0];
+    for y in (0..max_y).step_by(block_size as usize) {
+        for x in (0..max_x).step_by(block_size as usize) {
+            search.check_block(x, y, 0);
+        }
+    }
+}
 
-    #[cfg(feature = "visualize")]
-    let mut visualize_buf = BufWriter::new(stdout());
+struct PyramidSearch<'a, 'b, 'c> {
+    table: &'a [u32],
+    table_width: usize,
+    /// Positions go up to but don't include these
+    max_x: u32,
+    max_y: u32,
+    rect: &'a Rect,
+    reservoir: &'a mut Reservoir<'b>,
+    /// Only told about single positions, not whole blocks
+    on_check: CheckCallback<'c>,
+}
 
-    for y in 0..max_y {
-        let (furthest_right, furthest_left) = skip_list[y as usize];
-        for x in furthest_right..furthest_left.min(max_x as usize) {
-            let empty = region_is_empty(table, table_width as usize, x, y as usize, rect.width as usize, rect.height as usize);
-
-            #[cfg(feature = "visualize")]
-            {
-                let serialized = serde_json::to_string(&Message::CheckRectMessage(CheckRect {
-                    x: x as u32,
-                    y,
-                    empty,
-                })).unwrap();
-                writeln!(visualize_buf, "{}", serialized).unwrap();
-            };
+impl PyramidSearch<'_, '_, '_> {
+    fn check_block(&mut self, x: u32, y: u32, level: usize) {
+        let block_size = PYRAMID_LEVELS[level];
+        let last_x = (x + block_size).min(self.max_x) - 1;
+        let last_y = (y + block_size).min(self.max_y) - 1;
+        let (width, height) = (self.rect.width as usize, self.rect.height as usize);
 
+        if block_size == 1 {
+            let empty = region_is_empty(self.table, self.table_width, x as usize, y as usize, width, height);
+            if let Some(on_check) = self.on_check.as_mut() {
+                on_check(x, y, empty);
+            }
             if empty {
-                // https://en.wikipedia.org/wiki/Reservoir_sampling
-                let random_num: u32 = rng.generate_range(0..=available_points);
-                if random_num == available_points {
-                    random_point = Some(Point { x: x as u32, y });
-                }
-                available_points += 1;
+                self.reservoir.add(x, y);
             }
+            return;
+        }
+
+        // Covered by every rect in the block
+        let core_width = (x as usize + width).saturating_sub(last_x as usize);
+        let core_height = (y as usize + height).saturating_sub(last_y as usize);
+        if core_width > 0 && core_height > 0
+            && !region_is_empty(self.table, self.table_width, last_x as usize, last_y as usize, core_width, core_height) {
+            return;
         }
-    }
 
-    // println!("stopped_at: {} / {}", stopped_at, available_points);
-    // println!("{}", available_points);
+        // Covered by any rect in the block
+        let union_width = (last_x - x) as usize + width;
+        let union_height = (last_y - y) as usize + height;
+        if region_is_empty(self.table, self.table_width, x as usize, y as usize, union_width, union_height) {
+            self.reservoir.add_block(x, y, last_x - x + 1, last_y - y + 1);
+            return;
+        }
 
-    random_point
+        let sub_block_size = PYRAMID_LEVELS[level + 1];
+        for sub_y in (y..=last_y).step_by(sub_block_size as usize) {
+            for sub_x in (x..=last_x).step_by(sub_block_size as usize) {
+                self.check_block(sub_x, sub_y, level + 1);
+            }
+        }
+    }
 }
 
 pub fn to_summed_area_table(table: &mut [u32], width: usize, start_row: usize) {
@@ -141,9 +227,10 @@ pub fn to_summed_area_table(table: &mut [u32], width: usize, start_row: usize) {
             row.iter_mut()
                 .zip(prev_row.iter())
                 .for_each(|(el, prev_row_el)| {
+                    // Wraps on huge canvases, which region_is_empty accounts for
                     let original_value = *el;
-                    *el += sum + prev_row_el;
-                    sum += original_value;
+                    *el = el.wrapping_add(sum).wrapping_add(*prev_row_el);
+                    sum = sum.wrapping_add(original_value);
                 });
 
             prev_row.clone_from_slice(row);
@@ -183,4 +270,45 @@ mod tests {
         ];
         assert_eq!(table, expected);
     }
+
+    #[test]
+    fn overflowing_sat() {
+        let mut table = [0, 0, 0, 0, u32::MAX, 0, 0, 1, 0];
+        to_summed_area_table(&mut table, 3, 0);
+
+        assert_eq!(table, [0, 0, 0, 0, u32::MAX, u32::MAX, 0, 0, 0]);
+        assert!(!region_is_empty(&table, 3, 0, 0, 1, 1));
+        assert!(region_is_empty(&table, 3, 1, 1, 1, 1));
+    }
+
+    #[test]
+    fn pyramid_counts_every_free_position() {
+        let (width, height) = (200, 150);
+        let mut table = vec![0; width * height];
+        for (x, y) in [(10, 10), (150, 20), (100, 100), (199, 149), (60, 130)] {
+            table[y * width + x] = 1;
+        }
+        for x in 20..180 {
+            table[70 * width + x] = 1;
+        }
+        to_summed_area_table(&mut table, width, 0);
+
+        for rect in [Rect { width: 5, height: 3 }, Rect { width: 90, height: 40 }, Rect { width: 150, height: 10 }] {
+            let max_x = width as u32 - rect.width;
+            let max_y = height as u32 - rect.height;
+            let expected = (0..max_y)
+                .flat_map(|y| (0..max_x).map(move |x| (x, y)))
+                .filter(|(x, y)| region_is_empty(&table, width, *x as usize, *y as usize, rect.width as usize, rect.height as usize))
+                .count();
+
+            let mut rng = WyRand::new_seed(1);
+            let mut reservoir = Reservoir::new(None, &rect, width as u32, height as u32, &mut rng);
+            search_for_rect_pyramid(&table, width as u32, height as u32, &rect, &mut reservoir, None);
+
+            assert_eq!(reservoir.count(), expected as u64);
+
+            let point = reservoir.point.unwrap();
+            assert!(region_is_empty(&table, width, point.x as usize, point.y as usize, rect.width as usize, rect.height as usize));
+        }
+    }
 }
@@ -0,0 +1,112 @@
+use std::str::FromStr;
+
+/// How a word's weight is turned into a font size
+///
+/// Apart from [`FontSizeMapping::Relative`], every mapping places the heaviest word at the
+/// largest font size and the lightest word at the minimum font size.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum FontSizeMapping {
+    /// Scales each word's font size relative to the previous word, see
+    /// [`WordCloud::with_relative_font_scaling`](crate::WordCloud::with_relative_font_scaling)
+    #[default]
+    Relative,
+    /// Font size is proportional to the weight
+    Linear,
+    /// Font size is proportional to the square root of the weight
+    Sqrt,
+    /// Font size is proportional to the logarithm of the weight, which suits heavy-tailed
+    /// distributions
+    Log,
+    /// Font size decreases evenly with each word's rank, ignoring how far apart the weights are
+    Rank,
+    /// Words are grouped into this many evenly spaced font sizes by the logarithm of their weight,
+    /// like an HTML tag cloud
+    Tiers(u32),
+}
+
+impl FontSizeMapping {
+    /// Returns where a word falls between the minimum (`0.0`) and largest (`1.0`) font size,
+    /// or `None` for [`FontSizeMapping::Relative`]
+    pub fn position(&self, weight: f32, rank: usize, word_count: usize, min_weight: f32, max_weight: f32) -> Option<f32> {
+        let normalize = |value: f32, min: f32, max: f32| {
+            if max - min > f32::EPSILON {
+                ((value - min) / (max - min)).clamp(0.0, 1.0)
+            }
+            else {
+                1.0
+            }
+        };
+        let log = |value: f32| value.max(f32::MIN_POSITIVE).ln();
+
+        let position = match self {
+            FontSizeMapping::Relative => return None,
+            FontSizeMapping::Linear => normalize(weight, min_weight, max_weight),
+            FontSizeMapping::Sqrt => normalize(weight.sqrt(), min_weight.sqrt(), max_weight.sqrt()),
+            FontSizeMapping::Log => normalize(log(weight), log(min_weight), log(max_weight)),
+            FontSizeMapping::Rank => {
+                if word_count > 1 {
+                    1.0 - rank as f32 / (word_count - 1) as f32
+                }
+                else {
+                    1.0
+                }
+            },
+            FontSizeMapping::Tiers(tiers) => {
+                if *tiers > 1 {
+                    let position = normalize(log(weight), log(min_weight), log(max_weight));
+                    let tier = (position * *tiers as f32).floor().min(*tiers as f32 - 1.0);
+                    tier / (*tiers - 1) as f32
+                }
+                else {
+                    1.0
+                }
+            },
+        };
+
+        Some(position)
+    }
+}
+
+impl FromStr for FontSizeMapping {
+    type Err = String;
+
+    /// Parses a mapping name, where tiers can be given a count like `tiers:5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "relative" => Ok(FontSizeMapping::Relative),
+            "linear" => Ok(FontSizeMapping::Linear),
+            "sqrt" => Ok(FontSizeMapping::Sqrt),
+            "log" => Ok(FontSizeMapping::Log),
+            "rank" => Ok(FontSizeMapping::Rank),
+            "tiers" => Ok(FontSizeMapping::Tiers(6)),
+            other => match other.strip_prefix("tiers:").map(str::parse) {
+                Some(Ok(tiers)) => Ok(FontSizeMapping::Tiers(tiers)),
+                _ => Err(format!("Unknown font size mapping '{}'", s)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping_positions() {
+        let weights = [1.0, 0.25, 0.01];
+        let positions = |mapping: FontSizeMapping| {
+            weights.iter().enumerate()
+                .map(|(rank, weight)| mapping.position(*weight, rank, weights.len(), 0.01, 1.0).unwrap())
+                .map(|position| (position * 100.0).round() / 100.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(FontSizeMapping::Relative.position(0.5, 1, 3, 0.01, 1.0), None);
+        assert_eq!(positions(FontSizeMapping::Linear), vec![1.0, 0.24, 0.0]);
+        assert_eq!(positions(FontSizeMapping::Sqrt), vec![1.0, 0.44, 0.0]);
+        assert_eq!(positions(FontSizeMapping::Log), vec![1.0, 0.7, 0.0]);
+        assert_eq!(positions(FontSizeMapping::Rank), vec![1.0, 0.5, 0.0]);
+        assert_eq!(positions(FontSizeMapping::Tiers(4)), vec![1.0, 0.67, 0.0]);
+        assert_eq!("tiers:4".parse(), Ok(FontSizeMapping::Tiers(4)));
+    }
+}
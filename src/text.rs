@@ -1,5 +1,10 @@
-use ab_glyph::{point, Font, Glyph, Point, PxScale, ScaleFont, FontVec};
-use image::{GrayImage, Luma, Pixel, Rgb, Rgba, RgbaImage, RgbImage};
+use std::collections::HashMap;
+use ab_glyph::{point, Font, Glyph, GlyphImageFormat, Point, PxScale, ScaleFont, FontVec};
+use image::{ImageFormat, Pixel, Rgba, RgbaImage};
+use image::imageops::{self, FilterType};
+
+/// How many shaped runs or glyphs are kept before the cache starts over
+const MAX_CACHE_ENTRIES: usize = 1 << 16;
 
 #[derive(Clone, Debug)]
 pub struct GlyphData {
@@ -29,74 +34,258 @@ pub fn text_to_glyphs(text: &str, font: &FontVec, scale: PxScale) -> GlyphData {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw_glyphs_to_rgba_buffer(
     buffer: &mut RgbaImage,
     glyph_data: GlyphData,
     font: &FontVec,
+    cache: &mut GlyphCache,
     point: Point,
     rotate: bool,
     pixel: Rgba<u8>,
+    opacity: f32,
 ) {
     let width = glyph_data.width;
 
     for glyph in glyph_data.glyphs {
-        if let Some(outlined) = font.outline_glyph(glyph) {
-            let bounds = outlined.px_bounds();
+        let origin = ab_glyph::point(glyph.position.x.floor(), glyph.position.y.floor());
+
+        match cache.raster(font, &glyph) {
+            GlyphRaster::Outline { offset, width: raster_width, coverage } => {
+                let min = origin + *offset;
+
+                for (i, v) in coverage.iter().enumerate() {
+                    let (x, y) = (i as u32 % raster_width, i as u32 / raster_width);
+
+                    let (final_x, final_y) = if !rotate {
+                        (point.x as u32 + min.x as u32 + x, point.y as u32 + min.y as u32 + y)
+                    }
+                    else {
+                        (y + point.x as u32 + min.y as u32, (width + point.y as u32).saturating_sub(min.x as u32 + x))
+                    };
 
-            outlined.draw(|x, y, v| {
-                let (final_x, final_y) = if !rotate {
-                    (point.x as u32 + bounds.min.x as u32 + x, point.y as u32 + bounds.min.y as u32 + y)
+                    if final_x >= buffer.width() || final_y >= buffer.height() {
+                        continue;
+                    }
+
+                    let v = v * opacity;
+                    let px = buffer.get_pixel_mut(final_x, final_y);
+
+                    px.apply2(&pixel, |old, new| {
+                        ((v * new as f32) + (1.0 - v) * old as f32) as u8
+                    });
+
+                    if px != &Rgba::from([0; 4]) {
+                        px.0[3] = 0xFF;
+                    }
                 }
-                else {
-                    (y + point.x as u32 + bounds.min.y as u32, width + point.y as u32 - bounds.min.x as u32 - x)
-                };
+            },
+            // Color glyphs such as emoji are drawn with their own colors instead of the word's
+            GlyphRaster::Image { offset, image } => {
+                let min = origin + *offset;
 
-                let px = buffer.get_pixel_mut(final_x, final_y);
+                for (x, y, image_px) in image.enumerate_pixels() {
+                    let (final_x, final_y) = if !rotate {
+                        (point.x as u32 + min.x as u32 + x, point.y as u32 + min.y as u32 + y)
+                    }
+                    else {
+                        (y + point.x as u32 + min.y as u32, (width + point.y as u32).saturating_sub(min.x as u32 + x))
+                    };
 
-                px.apply2(&pixel, |old, new| {
-                    ((v * new as f32) + (1.0 - v) * old as f32) as u8
-                });
+                    if final_x >= buffer.width() || final_y >= buffer.height() {
+                        continue;
+                    }
 
-                if px != &Rgba::from([0; 4]) {
-                    px.0[3] = 0xFF;
+                    let v = image_px.0[3] as f32 / 255.0 * opacity;
+                    let px = buffer.get_pixel_mut(final_x, final_y);
+
+                    px.apply2(&image_px.to_rgba(), |old, new| {
+                        ((v * new as f32) + (1.0 - v) * old as f32) as u8
+                    });
+
+                    if v > 0.0 {
+                        px.0[3] = 0xFF;
+                    }
                 }
-            });
+            },
+            GlyphRaster::Empty => {},
         }
     }
 }
 
-pub fn draw_glyphs_to_gray_buffer(
-    buffer: &mut GrayImage,
+/// Calls `mark` for every pixel of the canvas that the glyphs cover
+pub fn mark_glyphs(
+    (canvas_width, canvas_height): (u32, u32),
     glyph_data: GlyphData,
     font: &FontVec,
+    cache: &mut GlyphCache,
     point: Point,
     rotate: bool,
+    mut mark: impl FnMut(u32, u32),
 ) {
     let width = glyph_data.width;
 
     for glyph in glyph_data.glyphs {
-        if let Some(outlined) = font.outline_glyph(glyph) {
-            let bounds = outlined.px_bounds();
+        let origin = ab_glyph::point(glyph.position.x.floor(), glyph.position.y.floor());
+
+        match cache.raster(font, &glyph) {
+            GlyphRaster::Outline { offset, width: raster_width, coverage } => {
+                let min = origin + *offset;
+
+                for (i, v) in coverage.iter().enumerate() {
+                    if *v < 0.05 {
+                        continue;
+                    }
 
-            outlined.draw(|x, y, v| {
-                if v < 0.05 {
-                    return;
+                    let (x, y) = (i as u32 % raster_width, i as u32 / raster_width);
+
+                    let (final_x, final_y) = if !rotate {
+                        (point.x as u32 + min.x as u32 + x, point.y as u32 + min.y as u32 + y)
+                    }
+                    else {
+                        (y + point.x as u32 + min.y as u32, width + point.y as u32 - min.x as u32 - x)
+                    };
+
+                    mark(final_x, final_y);
                 }
+            },
+            GlyphRaster::Image { offset, image } => {
+                let min = origin + *offset;
+
+                for (x, y, image_px) in image.enumerate_pixels() {
+                    if image_px.0[3] < 13 {
+                        continue;
+                    }
+
+                    let (final_x, final_y) = if !rotate {
+                        (point.x as u32 + min.x as u32 + x, point.y as u32 + min.y as u32 + y)
+                    }
+                    else {
+                        (y + point.x as u32 + min.y as u32, (width + point.y as u32).saturating_sub(min.x as u32 + x))
+                    };
 
-                let (final_x, final_y) = if !rotate {
-                    (point.x as u32 + bounds.min.x as u32 + x, point.y as u32 + bounds.min.y as u32 + y)
+                    if final_x < canvas_width && final_y < canvas_height {
+                        mark(final_x, final_y);
+                    }
                 }
-                else {
-                    (y + point.x as u32 + bounds.min.y as u32, width + point.y as u32 - bounds.min.x as u32 - x)
-                };
+            },
+            GlyphRaster::Empty => {},
+        }
+    }
+}
+
+/// A glyph drawn once at a given size and subpixel position, relative to the whole pixel its
+/// position falls in
+pub enum GlyphRaster {
+    Outline { offset: Point, width: u32, coverage: Vec<f32> },
+    Image { offset: Point, image: RgbaImage },
+    Empty,
+}
+
+type RasterKey = (u16, u32, u32, u32, u32);
+
+/// Remembers shaped text and rasterized glyphs so they aren't recomputed for every font size
+/// that is tried, or for every word cloud made with the same font
+#[derive(Default)]
+pub struct GlyphCache {
+    /// The font the cached entries belong to, so a different font starts a fresh cache
+    font: (usize, usize),
+    runs: HashMap<(String, u32, u32), GlyphData>,
+    rasters: HashMap<RasterKey, GlyphRaster>,
+}
+
+impl GlyphCache {
+    pub fn text_to_glyphs(&mut self, text: &str, font: &FontVec, scale: PxScale) -> GlyphData {
+        self.check_font(font);
+
+        let key = (text.to_string(), scale.x.to_bits(), scale.y.to_bits());
+        if let Some(glyph_data) = self.runs.get(&key) {
+            return glyph_data.clone();
+        }
+
+        if self.runs.len() >= MAX_CACHE_ENTRIES {
+            self.runs.clear();
+        }
+
+        let glyph_data = text_to_glyphs(text, font, scale);
+        self.runs.insert(key, glyph_data.clone());
+        glyph_data
+    }
 
-                let px = buffer.get_pixel_mut(final_x, final_y);
-                *px = Luma([1]);
-            });
+    pub fn raster(&mut self, font: &FontVec, glyph: &Glyph) -> &GlyphRaster {
+        self.check_font(font);
+
+        let origin = point(glyph.position.x.floor(), glyph.position.y.floor());
+        let subpixel = glyph.position - origin;
+        let key = (glyph.id.0, glyph.scale.x.to_bits(), glyph.scale.y.to_bits(), subpixel.x.to_bits(), subpixel.y.to_bits());
+
+        if !self.rasters.contains_key(&key) && self.rasters.len() >= MAX_CACHE_ENTRIES {
+            self.rasters.clear();
+        }
+
+        self.rasters.entry(key).or_insert_with(|| {
+            if let Some(outlined) = font.outline_glyph(glyph.clone()) {
+                let bounds = outlined.px_bounds();
+                let width = bounds.width() as u32;
+                let mut coverage = vec![0.0; (width * bounds.height() as u32) as usize];
+
+                outlined.draw(|x, y, v| {
+                    coverage[(y * width + x) as usize] = v;
+                });
+
+                GlyphRaster::Outline { offset: bounds.min - origin, width, coverage }
+            }
+            else if let Some((image, min)) = glyph_image(font, glyph) {
+                GlyphRaster::Image { offset: min - origin, image }
+            }
+            else {
+                GlyphRaster::Empty
+            }
+        })
+    }
+
+    fn check_font(&mut self, font: &FontVec) {
+        let id = (font.as_slice().as_ptr() as usize, font.as_slice().len());
+
+        if self.font != id {
+            self.font = id;
+            self.runs.clear();
+            self.rasters.clear();
         }
     }
 }
 
+/// Decodes the embedded bitmap of a glyph without an outline, such as a color emoji
+///
+/// Returns the image scaled to the glyph's size along with the position of its top left corner.
+fn glyph_image(font: &FontVec, glyph: &Glyph) -> Option<(RgbaImage, Point)> {
+    let pixels_per_em = glyph.scale.y * font.units_per_em()? / font.height_unscaled();
+    let glyph_image = font.glyph_raster_image2(glyph.id, pixels_per_em.ceil() as u16)?;
+
+    let image_format = match glyph_image.format {
+        GlyphImageFormat::Png => ImageFormat::Png,
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+
+    let decoded = image::load_from_memory_with_format(glyph_image.data, image_format)
+        .ok()?
+        .into_rgba8();
+
+    let factor = pixels_per_em / glyph_image.pixels_per_em as f32;
+    let width = (decoded.width() as f32 * factor).round().max(1.0) as u32;
+    let height = (decoded.height() as f32 * factor).round().max(1.0) as u32;
+    let resized = imageops::resize(&decoded, width, height, FilterType::Triangle);
+
+    // The origin is the offset of the bottom left corner of the image from the glyph's baseline
+    let min = point(
+        glyph.position.x + glyph_image.origin.x * factor,
+        glyph.position.y - glyph_image.origin.y * factor - height as f32,
+    );
+
+    Some((resized, min))
+}
+
 pub fn layout_paragraph<F, SF>(
     font: SF,
     position: Point,
@@ -136,3 +325,47 @@ pub fn layout_paragraph<F, SF>(
         target.push(glyph);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn cache_reuses_runs_and_rasters() {
+        let font = FontVec::try_from_vec(include_bytes!("../fonts/Ubuntu-B.ttf").to_vec()).unwrap();
+        let mut cache = GlyphCache::default();
+        let scale = PxScale::from(24.0);
+
+        let glyphs = cache.text_to_glyphs("cloud", &font, scale);
+        assert_eq!(glyphs.width, text_to_glyphs("cloud", &font, scale).width);
+        cache.text_to_glyphs("cloud", &font, scale);
+        assert_eq!(cache.runs.len(), 1);
+
+        let mut first = GrayImage::new(100, 40);
+        let mut second = GrayImage::new(100, 40);
+        mark_glyphs((100, 40), glyphs.clone(), &font, &mut cache, point(2.0, 2.0), false, |x, y| first.put_pixel(x, y, Luma([1])));
+        let rasters = cache.rasters.len();
+        mark_glyphs((100, 40), glyphs, &font, &mut cache, point(2.0, 2.0), false, |x, y| second.put_pixel(x, y, Luma([1])));
+
+        assert_eq!(cache.rasters.len(), rasters);
+        assert_eq!(first, second);
+        assert!(first.pixels().any(|p| p.0[0] == 1));
+    }
+    #[test]
+    fn draws_bitmap_glyphs_in_their_own_colors() {
+        // Only has bitmaps for a few symbols, such as the copyright sign
+        let font = FontVec::try_from_vec(include_bytes!("../fonts/NotoColorEmoji-Partial.ttf").to_vec()).unwrap();
+        let mut cache = GlyphCache::default();
+        let glyphs = cache.text_to_glyphs("\u{a9}", &font, PxScale::from(32.0));
+        assert!(matches!(cache.raster(&font, &glyphs.glyphs[0]), GlyphRaster::Image { .. }));
+
+        let mut buffer = RgbaImage::new(64, 64);
+        let black = Rgba([0, 0, 0, 255]);
+        draw_glyphs_to_rgba_buffer(&mut buffer, glyphs, &font, &mut cache, point(8.0, 8.0), false, black, 1.0);
+
+        let drawn = buffer.pixels().filter(|px| px.0[3] == 255).count();
+        assert!(drawn > 100);
+        assert!(buffer.pixels().any(|px| px.0[3] == 255 && px.0[..3] != black.0[..3]));
+    }
+}
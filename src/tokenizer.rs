@@ -1,16 +1,169 @@
-use regex::{Regex, Match};
+use regex::Regex;
+use std::borrow::Cow;
 use std::collections::{HashSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use crate::identifier::{split_identifier, Language};
 
 // TODO: Use lazy_static or PHF to make this a HashSet?
 pub const DEFAULT_EXCLUDE_WORDS_TEXT: &str = include_str!("../exclude_words.txt");
 
+const HTML_PATTERNS: &[&str] = &[
+    r"(?is)<script\b.*?</script\s*>",
+    r"(?is)<style\b.*?</style\s*>",
+    r"(?s)<!--.*?-->",
+    r"<[!?/]?[A-Za-z][^<>]*>",
+    r"&(?:#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z][A-Za-z0-9]*);",
+];
+
+const MARKDOWN_PATTERNS: &[&str] = &[
+    // Code fences along with their language
+    r"(?m)^[ \t]*(?:```|~~~)[^\n]*",
+    // Link and image destinations, keeping the link text
+    r"\]\([^)\n]*\)",
+    // Reference-style link definitions
+    r"(?m)^[ \t]*\[[^\]\n]+\]:[^\n]*",
+    // Underscore emphasis, which the default regex would otherwise count as part of the word
+    r"\b_+|_+\b",
+];
+
+/// Hashtags and mentions, emoji (including modifier and ZWJ sequences), flags, then regular words
+const SOCIAL_PATTERN: &str = concat!(
+    r"[#@]\w+",
+    r"|\p{Regional_Indicator}{2}",
+    r"|\p{Extended_Pictographic}(?:\x{FE0F}|\p{Emoji_Modifier}|\x{200D}\p{Extended_Pictographic}\x{FE0F}?)*",
+    r"|\w[\w']*",
+);
+
+const URL_PATTERN: &str = r#"(?i)\b(?:[a-z][a-z0-9+.-]*://|www\.)[^\s<>"'()\[\]]+"#;
+const EMAIL_PATTERN: &str = r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+";
+
+/// Turns text into the weighted words that make up a word cloud
+///
+/// [`Tokenizer`] is the built-in implementation. Implement this trait to plug a custom
+/// word source into [`WordCloud::with_tokenizer`](crate::WordCloud::with_tokenizer).
+pub trait Tokenize {
+    /// Returns the words to place along with their weights, sorted from heaviest to lightest
+    ///
+    /// Weights should be normalized so that the heaviest word has a weight of `1.0`.
+    fn word_weights<'a>(&'a self, text: &'a str) -> Vec<(Cow<'a, str>, f32)>;
+
+    /// Whether words are repeated in the output of [`Tokenize::word_weights`]
+    ///
+    /// Relative font scaling is not applied to repeated words.
+    fn repeats_words(&self) -> bool {
+        false
+    }
+
+    /// Whether two spellings count as the same word, which is how pinned words are matched to
+    /// the words of the text. Ignores case by default
+    fn same_word(&self, a: &str, b: &str) -> bool {
+        a.to_lowercase() == b.to_lowercase()
+    }
+}
+
+/// How the matches of a [`Tokenizer`]'s regex are turned into words
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TokenMode {
+    /// Every match is counted as a word
+    #[default]
+    Words,
+    /// Matches are treated as source code identifiers and split into their component words,
+    /// e.g. `parseHttpRequest` is counted as `parse`, `Http` and `Request`
+    Identifiers,
+    /// Social media text, where `#hashtags` and `@mentions` are kept as single words and emoji
+    /// are counted as words
+    ///
+    /// Selecting this mode with [`Tokenizer::with_mode`] replaces the tokenizer's regex.
+    Social,
+}
+
+/// How different casings of the same word are counted and displayed
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CaseStrategy {
+    /// Merges every casing of a word and displays the most frequent one
+    #[default]
+    MostCommon,
+    /// Counts every casing of a word as a separate word, e.g. "Apple" and "apple"
+    Sensitive,
+    /// Merges every casing of a word and displays it in lowercase
+    Lower,
+    /// Merges every casing of a word and displays it in uppercase
+    Upper,
+    /// Merges every casing of a word and displays it with only its first letter capitalized
+    Title,
+}
+
+impl FromStr for CaseStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "most-common" => Ok(CaseStrategy::MostCommon),
+            "sensitive" => Ok(CaseStrategy::Sensitive),
+            "lower" => Ok(CaseStrategy::Lower),
+            "upper" => Ok(CaseStrategy::Upper),
+            "title" => Ok(CaseStrategy::Title),
+            _ => Err(format!("Unknown case strategy '{}'", s)),
+        }
+    }
+}
+
+impl CaseStrategy {
+    fn display<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        match self {
+            CaseStrategy::MostCommon | CaseStrategy::Sensitive => Cow::Borrowed(word),
+            CaseStrategy::Lower => Cow::Owned(word.to_lowercase()),
+            CaseStrategy::Upper => Cow::Owned(word.to_uppercase()),
+            CaseStrategy::Title => {
+                let lower = word.to_lowercase();
+                match lower.find(char::is_alphabetic) {
+                    Some(i) => {
+                        let first = lower[i..].chars().next().unwrap();
+                        let rest = &lower[i + first.len_utf8()..];
+                        Cow::Owned(format!("{}{}{}", &lower[..i], first.to_uppercase(), rest))
+                    },
+                    None => Cow::Owned(lower),
+                }
+            }
+        }
+    }
+
+    /// The key that words are counted under
+    fn key(&self, word: &str) -> String {
+        match self {
+            CaseStrategy::Sensitive => word.to_string(),
+            _ => word.to_lowercase(),
+        }
+    }
+}
+
 pub struct Tokenizer {
     pub regex: Regex,
+    pub mode: TokenMode,
+    pub case_strategy: CaseStrategy,
     filter: HashSet<String>,
+    filter_patterns: Vec<Regex>,
+    keywords: HashSet<&'static str>,
+    aliases: HashMap<String, String>,
+    include: HashSet<String>,
+    include_patterns: Vec<Regex>,
     pub min_word_length: u32,
     pub exclude_numbers: bool,
     pub max_words: u32,
+    pub min_frequency: u32,
+    pub max_frequency: Option<u32>,
+    pub max_document_share: Option<f32>,
     pub repeat: bool,
+    pub strip_html: bool,
+    pub strip_markdown: bool,
+    pub strip_urls: bool,
+    pub strip_emails: bool,
+    pub hashtags_only: bool,
+    /// Compiled on first use from the `strip_*` flags
+    markup_regexes: OnceLock<Vec<Regex>>,
 }
 
 impl Default for Tokenizer {
@@ -25,31 +178,159 @@ impl Default for Tokenizer {
 
         Tokenizer {
             regex,
+            mode: TokenMode::Words,
+            case_strategy: CaseStrategy::MostCommon,
             filter,
+            filter_patterns: Vec::new(),
+            keywords: HashSet::new(),
+            aliases: HashMap::new(),
+            include: HashSet::new(),
+            include_patterns: Vec::new(),
             min_word_length: 0,
             exclude_numbers: true,
             max_words: 200,
+            min_frequency: 0,
+            max_frequency: None,
+            max_document_share: None,
             repeat: false,
+            strip_html: false,
+            strip_markdown: false,
+            strip_urls: false,
+            strip_emails: false,
+            hashtags_only: false,
+            markup_regexes: OnceLock::new(),
         }
     }
 }
 
 impl<'a> Tokenizer {
-    fn tokenize(&'a self, text: &'a str) -> Box<dyn Iterator<Item=Match<'a>> + 'a> {
-        let mut result: Box<dyn Iterator<Item=Match<'a>> + 'a>
-            = Box::new(self.regex.find_iter(text));
-
-        if !self.filter.is_empty() {
-            result = Box::new(result.filter(move |word| {
-                let word_lower = word.as_str().to_lowercase();
-                !self.filter.contains(word_lower.as_str())
+    /// Splits the text into the parts that are left over after removing markup
+    ///
+    /// Words are never joined across removed markup, so `wo<b>rd</b>` stays two words.
+    fn markup_regexes(&self) -> &[Regex] {
+        self.markup_regexes.get_or_init(|| {
+            let mut patterns = Vec::new();
+            if self.strip_html {
+                patterns.extend_from_slice(HTML_PATTERNS);
+            }
+            if self.strip_markdown {
+                patterns.extend_from_slice(MARKDOWN_PATTERNS);
+            }
+            if self.strip_urls {
+                patterns.push(URL_PATTERN);
+            }
+            if self.strip_emails {
+                patterns.push(EMAIL_PATTERN);
+            }
+
+            patterns.iter()
+                .map(|pattern| Regex::new(pattern).expect("Unable to compile markup regex"))
+                .collect()
+        })
+    }
+
+    /// The byte ranges of the text that are left over after removing markup
+    fn strip_markup(&self, text: &str) -> Vec<(usize, usize)> {
+        let regexes = self.markup_regexes();
+        if regexes.is_empty() {
+            return vec![(0, text.len())];
+        }
+
+        let mut removed: Vec<(usize, usize)> = regexes.iter()
+            .flat_map(|regex| regex.find_iter(text).map(|m| (m.start(), m.end())))
+            .collect();
+        removed.sort_unstable();
+
+        let mut segments = Vec::new();
+        let mut start = 0;
+        for (removed_start, removed_end) in removed {
+            if removed_start > start {
+                segments.push((start, removed_start));
+            }
+            start = start.max(removed_end);
+        }
+        segments.push((start, text.len()));
+
+        segments
+    }
+
+    /// Splits what is left of the text after removing markup into lines, along with the index of
+    /// the line of the text each one is on
+    ///
+    /// Markup is removed from the whole text first, so a code fence or script block spanning
+    /// several lines is removed as a whole.
+    fn lines(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        let mut lines = Vec::new();
+        let mut line = 0;
+        let mut end = 0;
+
+        for (start, segment_end) in self.strip_markup(text) {
+            // Line breaks inside removed markup still count
+            line += text[end..start].matches('\n').count();
+
+            for (i, part) in text[start..segment_end].split('\n').enumerate() {
+                if i > 0 {
+                    line += 1;
+                }
+                lines.push((line, part));
+            }
+
+            end = segment_end;
+        }
+
+        lines
+    }
+
+    /// The words of the lines from [`Tokenizer::lines`] along with the index of the line they are on
+    fn tokenize(&'a self, lines: Vec<(usize, &'a str)>) -> Box<dyn Iterator<Item=(usize, &'a str)> + 'a> {
+        let mut result: Box<dyn Iterator<Item=(usize, &'a str)> + 'a>
+            = Box::new(lines.into_iter().flat_map(move |(line, part)| {
+                self.regex.find_iter(part).map(move |word| (line, word.as_str()))
+            }));
+
+        if !self.keywords.is_empty() {
+            result = Box::new(result.filter(move |(_, word)| !self.keywords.contains(word)));
+        }
+        if self.mode == TokenMode::Identifiers {
+            result = Box::new(result.flat_map(|(line, word)| {
+                split_identifier(word).into_iter().map(move |part| (line, part))
+            }));
+        }
+        if !self.aliases.is_empty() {
+            result = Box::new(result.map(move |(line, word)| {
+                let word = self.aliases.get(word.to_lowercase().as_str())
+                    .map_or(word, String::as_str);
+                (line, word)
+            }));
+        }
+        if !self.include.is_empty() || !self.include_patterns.is_empty() {
+            result = Box::new(result.filter(move |(_, word)| {
+                let word_lower = word.to_lowercase();
+                self.include.contains(word_lower.as_str())
+                    || self.include_patterns.iter().any(|pattern| pattern.is_match(word))
             }));
         }
+        else {
+            if !self.filter.is_empty() {
+                result = Box::new(result.filter(move |(_, word)| {
+                    let word_lower = word.to_lowercase();
+                    !self.filter.contains(word_lower.as_str())
+                }));
+            }
+            if !self.filter_patterns.is_empty() {
+                result = Box::new(result.filter(move |(_, word)| {
+                    !self.filter_patterns.iter().any(|pattern| pattern.is_match(word))
+                }));
+            }
+        }
         if self.min_word_length > 0 {
-            result = Box::new(result.filter(move |word| word.as_str().len() >= self.min_word_length as usize));
+            result = Box::new(result.filter(move |(_, word)| word.len() >= self.min_word_length as usize));
         }
         if self.exclude_numbers {
-            result = Box::new(result.filter(move |word| !word.as_str().chars().all(char::is_numeric)));
+            result = Box::new(result.filter(move |(_, word)| !word.chars().all(char::is_numeric)));
+        }
+        if self.hashtags_only {
+            result = Box::new(result.filter(move |(_, word)| word.starts_with('#')));
         }
 
         result
@@ -87,30 +368,78 @@ impl<'a> Tokenizer {
 
     fn get_word_frequencies(&'a self, text: &'a str) -> (HashMap<&'a str, usize>, usize) {
         let mut frequencies = HashMap::new();
+        // How many lines each word is on, along with the last line it was counted for
+        let mut document_counts = HashMap::<String, (usize, usize)>::new();
+
+        let lines = self.lines(text);
+        // Each line with anything left on it after removing markup is a document
+        let mut documents = 0;
+        let mut last_document = None;
+        for (line, part) in &lines {
+            if !part.trim().is_empty() && last_document != Some(*line) {
+                documents += 1;
+                last_document = Some(*line);
+            }
+        }
 
-        let included_words = self.tokenize(text);
+        let included_words = self.tokenize(lines);
 
-        for word in included_words {
-            let entry = frequencies.entry(word.as_str()).or_insert(0);
+        for (line, word) in included_words {
+            let entry = frequencies.entry(word).or_insert(0);
             *entry += 1;
+
+            if self.max_document_share.is_some() {
+                let (count, last_line) = document_counts.entry(self.case_strategy.key(word))
+                    .or_insert((0, usize::MAX));
+
+                // Words come in the order of the text, so each line is only counted once
+                if *last_line != line {
+                    *count += 1;
+                    *last_line = line;
+                }
+            }
+        }
+
+        let mut common_cased_map = if self.case_strategy == CaseStrategy::Sensitive {
+            frequencies
         }
+        else {
+            Self::keep_common_case(&frequencies)
+        };
+        self.apply_frequency_rules(&mut common_cased_map, documents, &document_counts);
 
-        let common_cased_map = Self::keep_common_case(&frequencies);
-        let max_freq = *common_cased_map.values().max()
-            .expect("Can't get max frequency");
+        let max_freq = common_cased_map.values().max()
+            .copied()
+            .unwrap_or(0);
 
         (common_cased_map, max_freq)
     }
 
-    pub fn get_normalized_word_frequencies(&'a self, text: &'a str) -> Vec<(&'a str, f32)> {
+    /// Removes words that are too rare, too common, or appear in too many of the documents
+    fn apply_frequency_rules(&self, frequencies: &mut HashMap<&'a str, usize>, documents: usize, document_counts: &HashMap<String, (usize, usize)>) {
+        frequencies.retain(|_, count| {
+            *count >= self.min_frequency as usize
+                && self.max_frequency.is_none_or(|max| *count <= max as usize)
+        });
+
+        if let Some(max_share) = self.max_document_share {
+            let max_documents = max_share * documents as f32;
+            frequencies.retain(|word, _| {
+                let count = document_counts.get(&self.case_strategy.key(word)).map_or(0, |(count, _)| *count);
+                count as f32 <= max_documents
+            });
+        }
+    }
+
+    pub fn get_normalized_word_frequencies(&'a self, text: &'a str) -> Vec<(Cow<'a, str>, f32)> {
         let (frequencies, max_freq) = self.get_word_frequencies(text);
 
         if frequencies.is_empty() {
             return Vec::new();
         }
 
-        let mut normalized_freqs: Vec<(&str, f32)> = frequencies.iter().map(|(key, val)| {
-            (*key, *val as f32 / max_freq as f32)
+        let mut normalized_freqs: Vec<(Cow<str>, f32)> = frequencies.iter().map(|(key, val)| {
+            (self.case_strategy.display(key), *val as f32 / max_freq as f32)
         }).collect();
 
         normalized_freqs.sort_by(|a, b| {
@@ -118,7 +447,7 @@ impl<'a> Tokenizer {
                 (b.1).partial_cmp(&a.1).unwrap()
             }
             else {
-                (a.0).partial_cmp(b.0).unwrap()
+                (a.0).cmp(&b.0)
             }
         });
 
@@ -137,7 +466,7 @@ impl<'a> Tokenizer {
             for i in 1..=times_extend {
                 normalized_freqs.extend(
                     freqs_clone.iter().map(|(word, freq)| {
-                        (*word, freq * down_weight.powf(i as f32))
+                        (word.clone(), freq * down_weight.powf(i as f32))
                     })
                 )
             }
@@ -150,6 +479,63 @@ impl<'a> Tokenizer {
         self.regex = value;
         self
     }
+    pub fn with_case_strategy(mut self, value: CaseStrategy) -> Self {
+        self.case_strategy = value;
+        self
+    }
+    /// Social mode replaces the tokenization regex, including one set with [`Tokenizer::with_regex`]
+    pub fn with_mode(mut self, value: TokenMode) -> Self {
+        if value == TokenMode::Social {
+            self.regex = Regex::new(SOCIAL_PATTERN)
+                .expect("Unable to compile social media tokenization regex");
+        }
+
+        self.mode = value;
+        self
+    }
+    /// Excludes the keywords of the given languages, matched case-sensitively against whole regex matches
+    pub fn with_keywords_excluded(mut self, value: &[Language]) -> Self {
+        self.keywords = value.iter()
+            .flat_map(|language| language.keywords())
+            .copied()
+            .collect();
+
+        self
+    }
+    /// Replaces words with a canonical form before they are counted, e.g. `js` with `JavaScript`
+    ///
+    /// The keys are matched case-insensitively. The canonical words are also used as the display
+    /// form for any other casing of themselves.
+    pub fn with_aliases(mut self, value: HashMap<&str, &str>) -> Self {
+        self.aliases = value.iter()
+            .flat_map(|(variant, canonical)| [(*variant, *canonical), (*canonical, *canonical)])
+            .map(|(variant, canonical)| (variant.to_lowercase(), canonical.to_string()))
+            .collect();
+
+        self
+    }
+    /// Loads aliases from a file, see [`parse_aliases`] for the format
+    pub fn with_aliases_from_path(self, path: PathBuf) -> Self {
+        let text = fs::read_to_string(path)
+            .expect("Unable to read aliases file");
+
+        self.with_aliases(parse_aliases(&text))
+    }
+    /// Only counts the given words, matched case-insensitively, ignoring every other word
+    ///
+    /// Words that are included are counted even if they are part of the exclusion filter.
+    pub fn with_include_words(mut self, value: HashSet<&str>) -> Self {
+        self.include = value.iter()
+            .map(|el| el.to_lowercase())
+            .collect();
+
+        self
+    }
+    /// Also counts any word that the given regexes match, see [`Tokenizer::with_include_words`]
+    pub fn with_include_patterns(mut self, value: Vec<Regex>) -> Self {
+        self.include_patterns = value;
+        self
+    }
     pub fn with_filter(mut self, value: HashSet<&str>) -> Self {
         self.filter = value.iter()
             .map(|el| el.to_lowercase())
@@ -157,6 +543,30 @@ impl<'a> Tokenizer {
 
         self
     }
+    /// Excludes any word that one of the regexes matches, in addition to [`Tokenizer::with_filter`]
+    pub fn with_filter_patterns(mut self, value: Vec<Regex>) -> Self {
+        self.filter_patterns = value;
+        self
+    }
+    /// Excludes words that occur fewer times than this
+    pub fn with_min_frequency(mut self, value: u32) -> Self {
+        self.min_frequency = value;
+        self
+    }
+    /// Excludes words that occur more times than this
+    pub fn with_max_frequency(mut self, value: Option<u32>) -> Self {
+        self.max_frequency = value;
+        self
+    }
+    /// Excludes words that appear in more than this fraction (0.0 - 1.0) of the documents,
+    /// where every line that isn't empty once markup is removed is a document
+    pub fn with_max_document_share(mut self, value: Option<f32>) -> Self {
+        if let Some(share) = value {
+            assert!((0.0..=1.0).contains(&share), "The maximum document share must be between 0 and 1");
+        }
+        self.max_document_share = value;
+        self
+    }
     pub fn with_min_word_length(mut self, value: u32) -> Self {
         self.min_word_length = value;
         self
@@ -173,6 +583,112 @@ impl<'a> Tokenizer {
         self.repeat = value;
         self
     }
+    /// Removes HTML/XML tags, comments, `<script>` and `<style>` blocks, and entities before tokenizing
+    pub fn with_strip_html(mut self, value: bool) -> Self {
+        self.strip_html = value;
+        self.markup_regexes = OnceLock::new();
+        self
+    }
+    /// Removes Markdown syntax such as code fences, link destinations and reference definitions before tokenizing
+    pub fn with_strip_markdown(mut self, value: bool) -> Self {
+        self.strip_markdown = value;
+        self.markup_regexes = OnceLock::new();
+        self
+    }
+    /// Only counts `#hashtags`, ignoring every other word. This switches to [`TokenMode::Social`],
+    /// since the other modes never keep the `#`
+    pub fn with_hashtags_only(mut self, value: bool) -> Self {
+        if value && self.mode != TokenMode::Social {
+            self = self.with_mode(TokenMode::Social);
+        }
+
+        self.hashtags_only = value;
+        self
+    }
+    pub fn with_strip_urls(mut self, value: bool) -> Self {
+        self.strip_urls = value;
+        self.markup_regexes = OnceLock::new();
+        self
+    }
+    pub fn with_strip_emails(mut self, value: bool) -> Self {
+        self.strip_emails = value;
+        self.markup_regexes = OnceLock::new();
+        self
+    }
+}
+
+/// Parses an alias table where each line maps a comma-separated list of variants to a canonical word
+///
+/// ```text
+/// # Lines starting with a '#' are ignored
+/// JavaScript: js, ecmascript
+/// Kubernetes: k8s, kube
+/// ```
+pub fn parse_aliases(text: &str) -> HashMap<&str, &str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .flat_map(|(canonical, variants)| {
+            variants.split(',')
+                .map(str::trim)
+                .filter(|variant| !variant.is_empty())
+                .map(move |variant| (variant, canonical.trim()))
+        })
+        .collect()
+}
+
+/// Parses a newline-separated list of words
+///
+/// Lines wrapped in slashes like `/^k8s.*/` are regexes, and lines containing `*` or `?` are
+/// case-insensitive globs like `uh*`. Both are returned as regexes.
+pub fn parse_word_list(text: &str) -> Result<(HashSet<&str>, Vec<Regex>), regex::Error> {
+    let mut words = HashSet::new();
+    let mut patterns = Vec::new();
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(pattern) = line.strip_prefix('/').and_then(|line| line.strip_suffix('/')) {
+            patterns.push(Regex::new(pattern)?);
+        }
+        else if line.contains(['*', '?']) {
+            patterns.push(glob_to_regex(line)?);
+        }
+        else {
+            words.insert(line);
+        }
+    }
+
+    Ok((words, patterns))
+}
+
+/// Converts a glob where `*` matches any number of characters and `?` matches one character
+/// into a case-insensitive regex that matches whole words
+pub fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let pattern = glob.split('*')
+        .map(|part| {
+            part.split('?')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+        .collect::<Vec<_>>()
+        .join(".*");
+
+    Regex::new(&format!("(?i)^{}$", pattern))
+}
+
+impl Tokenize for Tokenizer {
+    fn word_weights<'a>(&'a self, text: &'a str) -> Vec<(Cow<'a, str>, f32)> {
+        self.get_normalized_word_frequencies(text)
+    }
+
+    fn repeats_words(&self) -> bool {
+        self.repeat
+    }
+
+    fn same_word(&self, a: &str, b: &str) -> bool {
+        self.case_strategy.key(a) == self.case_strategy.key(b)
+    }
 }
 
 #[cfg(test)]
@@ -203,6 +719,9 @@ mod tests {
             .with_repeat(true)
             .with_max_words(12);
         let frequencies = tokenizer.get_normalized_word_frequencies(words);
+        let frequencies: Vec<(&str, f32)> = frequencies.iter()
+            .map(|(word, freq)| (word.as_ref(), *freq))
+            .collect();
 
         let expected = vec![
             ("could", 1.0), ("a", 0.6666667), ("chuck", 0.6666667), ("wood", 0.33333334),
@@ -246,4 +765,213 @@ mod tests {
 
         assert_eq!(frequencies.0, expected);
     }
+
+    #[test]
+    fn custom_tokenizer_through_trait() {
+        struct Fixed;
+
+        impl Tokenize for Fixed {
+            fn word_weights<'a>(&'a self, _text: &'a str) -> Vec<(Cow<'a, str>, f32)> {
+                vec![(Cow::Owned("rust".to_string()), 1.0), (Cow::Borrowed("cloud"), 0.5)]
+            }
+        }
+
+        let tokenizers: Vec<Box<dyn Tokenize>> = vec![Box::new(Fixed), Box::new(Tokenizer::default())];
+        let weights: Vec<_> = tokenizers.iter()
+            .map(|tokenizer| tokenizer.word_weights("cloud cloud rust"))
+            .collect();
+
+        assert_eq!(weights[0], vec![(Cow::Borrowed("rust"), 1.0), (Cow::Borrowed("cloud"), 0.5)]);
+        assert_eq!(weights[1], vec![(Cow::Borrowed("cloud"), 1.0), (Cow::Borrowed("rust"), 0.5)]);
+        assert!(!tokenizers[1].repeats_words());
+    }
+
+    #[test]
+    fn strips_markup() {
+        let text = r#"
+            <!DOCTYPE html>
+            <p class="intro">Clouds&nbsp;are <a href="https://example.com/clouds">great</a></p>
+            <script>var hidden = true;</script>
+            # Clouds
+            See the [_docs_](https://docs.rs/wcloud) or www.example.org
+            ```rust
+            [docs]: https://docs.rs/wcloud "Docs"
+            Mail someone@example.com
+        "#;
+
+        let tokenizer = Tokenizer::default()
+            .with_filter(HashSet::new())
+            .with_strip_html(true)
+            .with_strip_markdown(true)
+            .with_strip_urls(true)
+            .with_strip_emails(true);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("Clouds", 2), ("are", 1), ("great", 1), ("See", 1), ("the", 1), ("docs", 1),
+            ("or", 1), ("Mail", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
+
+    #[test]
+    fn social_mode_keeps_hashtags_mentions_and_emoji() {
+        let text = "Loving the #RustLang meetup with @ferris 🦀🦀 👍🏽 #rustlang 🇳🇱 #2023";
+
+        let tokenizer = Tokenizer::default()
+            .with_mode(TokenMode::Social);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("Loving", 1), ("#rustlang", 2), ("meetup", 1), ("@ferris", 1), ("🦀", 2), ("👍🏽", 1),
+            ("🇳🇱", 1), ("#2023", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+
+        // Switches to social mode by itself
+        let tokenizer = Tokenizer::default().with_hashtags_only(true);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("#rustlang", 2), ("#2023", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
+
+    #[test]
+    fn aliases_merge_variants() {
+        let text = "JS js k8s Kubernetes kube javascript Rust";
+        let aliases = parse_aliases("
+            # Languages
+            JavaScript: js
+            Kubernetes: k8s, kube
+        ");
+
+        let tokenizer = Tokenizer::default()
+            .with_aliases(aliases);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("JavaScript", 3), ("Kubernetes", 3), ("Rust", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
+
+    #[test]
+    fn include_words_only() {
+        let text = "We moved the Rust services to k8s and kubernetes-1.27, not the Go ones";
+        let (words, patterns) = parse_word_list("rust\nthe\n/^k(8s|ubernetes)$/\n").unwrap();
+
+        let tokenizer = Tokenizer::default()
+            .with_include_words(words)
+            .with_include_patterns(patterns);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("Rust", 1), ("the", 2), ("k8s", 1), ("kubernetes", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
+
+    #[test]
+    fn filter_patterns_and_frequency_rules() {
+        let text = "uh okay uhh\nuhhh okay fine\nokay fine Fine\nUh done";
+        let (words, patterns) = parse_word_list("uh*\n/^ok/\ndone").unwrap();
+
+        let tokenizer = Tokenizer::default()
+            .with_filter(words)
+            .with_filter_patterns(patterns);
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("fine", 3)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+
+        let text = "one two two three three three\nthree two\nthree four";
+        let tokenizer = Tokenizer::default()
+            .with_min_frequency(2)
+            .with_max_frequency(Some(4));
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("two", 3)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+
+        let tokenizer = Tokenizer::default()
+            .with_max_document_share(Some(0.5));
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("one", 1), ("four", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+
+        // Markup spanning several lines isn't counted towards the documents a word is in, and
+        // lines that are only markup aren't documents
+        let text = "rust crate\n<br>\ncargo\n<script>\ncargo\ncargo\n</script>\nrust";
+        let tokenizer = Tokenizer::default()
+            .with_strip_html(true)
+            .with_max_document_share(Some(0.5));
+        let frequencies = tokenizer.get_word_frequencies(text);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("crate", 1), ("cargo", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
+
+    #[test]
+    fn case_strategies() {
+        let words = "Apple apple apple APPLE pie";
+        let counts = |strategy| {
+            Tokenizer::default()
+                .with_case_strategy(strategy)
+                .get_normalized_word_frequencies(words)
+                .into_iter()
+                .map(|(word, freq)| (word.into_owned(), freq))
+                .collect::<Vec<_>>()
+        };
+        let owned = |expected: &[(&str, f32)]| {
+            expected.iter().map(|(word, freq)| (word.to_string(), *freq)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(counts(CaseStrategy::MostCommon), owned(&[("apple", 1.0), ("pie", 0.25)]));
+        assert_eq!(counts(CaseStrategy::Sensitive), owned(&[("apple", 1.0), ("APPLE", 0.5), ("Apple", 0.5), ("pie", 0.5)]));
+        assert_eq!(counts(CaseStrategy::Upper), owned(&[("APPLE", 1.0), ("PIE", 0.25)]));
+        assert_eq!(counts(CaseStrategy::Title), owned(&[("Apple", 1.0), ("Pie", 0.25)]));
+    }
+
+    #[test]
+    fn identifier_mode_splits_and_strips_keywords() {
+        let code = r#"
+            // Parse the HTTPRequest
+            fn parse_request(raw_request: &str) -> HttpRequest {
+                let parsed = parseHttpRequest("raw-request");
+            }
+        "#;
+
+        let tokenizer = Tokenizer::default()
+            .with_filter(HashSet::new())
+            .with_mode(TokenMode::Identifiers)
+            .with_keywords_excluded(&[Language::Rust]);
+        let frequencies = tokenizer.get_word_frequencies(code);
+
+        let expected: HashMap<&str, usize> = vec![
+            ("parse", 3), ("the", 1), ("Http", 3), ("request", 6), ("raw", 2), ("str", 1),
+            ("parsed", 1)
+        ].into_iter().collect();
+
+        assert_eq!(frequencies.0, expected);
+    }
 }
@@ -1,11 +1,14 @@
 use serde_derive::Serialize;
 
+/// The variant names are part of the JSON format the visualizer reads
+#[allow(clippy::enum_variant_names)]
 #[derive(Serialize)]
 pub enum Message {
     InitMessage(Init),
     ChangeWordMessage(Word),
     CheckRectMessage(CheckRect),
     PlacedWordMessage(PlaceWord),
+    DroppedWordMessage(DroppedWord),
 }
 
 #[derive(Serialize)]
@@ -13,7 +16,9 @@ pub struct Init {
     pub width: u32,
     pub height: u32,
     pub mask: Option<Vec<u8>>,
-    pub font: Vec<u8>,
+    /// Only included when asked for with [`crate::JsonObserver::with_font`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<Vec<u8>>,
     pub background_color: [u8; 4],
 }
 
@@ -42,4 +47,8 @@ pub struct PlaceWord {
     pub y: u32,
 }
 
-
+#[derive(Serialize)]
+pub struct DroppedWord {
+    pub text: String,
+    pub reason: &'static str,
+}